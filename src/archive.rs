@@ -0,0 +1,221 @@
+use crate::error::EmulateError;
+
+// ROMs can be loaded straight out of a zip as ARCHIVE.zip:ENTRY. Only
+// stored entries are read, ROMs are small enough that most collections
+// don't bother compressing them.
+
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4B50;
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+
+// Method of an entry kept as it is, without compression
+const STORED: u16 = 0;
+
+// Entry listed in an archive's central directory
+struct Entry {
+    name: String,
+    method: u16,
+    size: usize,
+    offset: usize,
+}
+
+// Splits ARCHIVE.zip:ENTRY into the archive and entry. None for a path that
+// doesn't name an entry in a zip.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let at = path.to_ascii_lowercase().find(".zip:")? + ".zip".len();
+    Some((&path[..at], &path[at + 1..]))
+}
+
+// Reads a ROM from a path, out of an archive if it names an entry in one
+pub fn read_path(path: &str) -> Result<Vec<u8>, EmulateError> {
+    match split(path) {
+        Some((archive, name)) => read(&std::fs::read(archive)?, archive, name),
+        None => Ok(std::fs::read(path)?),
+    }
+}
+
+// Names of the files in an archive, in the order they were stored
+pub fn list(data: &[u8], archive: &str) -> Result<Vec<String>, EmulateError> {
+    Ok(entries(data, archive)?
+        .into_iter()
+        .map(|entry| entry.name)
+        .filter(|name| !name.ends_with('/'))
+        .collect())
+}
+
+// Contents of the entry with the given name
+pub fn read(data: &[u8], archive: &str, name: &str) -> Result<Vec<u8>, EmulateError> {
+    let invalid = || EmulateError::InvalidArchive(archive.to_string());
+
+    let entry = entries(data, archive)?
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| EmulateError::MissingEntry(archive.to_string(), name.to_string()))?;
+    if entry.method != STORED {
+        return Err(EmulateError::CompressedEntry(
+            archive.to_string(),
+            name.to_string(),
+        ));
+    }
+
+    if u32_at(data, entry.offset) != Some(LOCAL_HEADER) {
+        return Err(invalid());
+    }
+    let name_len = u16_at(data, entry.offset + 26).ok_or_else(invalid)? as usize;
+    let extra_len = u16_at(data, entry.offset + 28).ok_or_else(invalid)? as usize;
+    let start = entry.offset + 30 + name_len + extra_len;
+
+    data.get(start..start + entry.size)
+        .map(|bytes| bytes.to_vec())
+        .ok_or_else(invalid)
+}
+
+// Entries in the central directory, found through the record at the end of
+// the archive
+fn entries(data: &[u8], archive: &str) -> Result<Vec<Entry>, EmulateError> {
+    let invalid = || EmulateError::InvalidArchive(archive.to_string());
+
+    // The end record is last, followed only by a comment of up to 64K
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .take(0x1_0000)
+        .find(|&at| u32_at(data, at) == Some(END_OF_DIRECTORY))
+        .ok_or_else(invalid)?;
+    let count = u16_at(data, end + 10).ok_or_else(invalid)?;
+    let mut at = u32_at(data, end + 16).ok_or_else(invalid)? as usize;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        if u32_at(data, at) != Some(DIRECTORY_ENTRY) {
+            return Err(invalid());
+        }
+        let field = |offset| u16_at(data, at + offset).ok_or_else(invalid);
+        let name_len = field(28)? as usize;
+        let extra_len = field(30)? as usize;
+        let comment_len = field(32)? as usize;
+
+        let name = data.get(at + 46..at + 46 + name_len).ok_or_else(invalid)?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: field(10)?,
+            size: u32_at(data, at + 20).ok_or_else(invalid)? as usize,
+            offset: u32_at(data, at + 42).ok_or_else(invalid)? as usize,
+        });
+
+        at += 46 + name_len + extra_len + comment_len;
+    }
+
+    Ok(entries)
+}
+
+// Little endian values, None past the end of the data
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Archive holding each file as a stored entry, as `zip -0` writes it
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+
+        for (name, contents) in files.iter() {
+            let offset = data.len() as u32;
+            let size = contents.len() as u32;
+
+            data.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            // Version, flags, method, time, date and CRC, unchecked here
+            data.extend_from_slice(&[0; 14]);
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(contents);
+
+            directory.extend_from_slice(&DIRECTORY_ENTRY.to_le_bytes());
+            // Versions, flags, method, time, date and CRC
+            directory.extend_from_slice(&[0; 16]);
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            // Extra and comment lengths, disk, attributes
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let start = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn path_names_an_entry_after_the_archive() {
+        assert_eq!(split("roms.zip:UFO"), Some(("roms.zip", "UFO")));
+        assert_eq!(split("ROMS.ZIP:games/UFO"), Some(("ROMS.ZIP", "games/UFO")));
+        assert_eq!(split("UFO"), None);
+        assert_eq!(split("roms.zip"), None);
+    }
+
+    #[test]
+    fn entry_is_read_from_an_in_memory_zip() {
+        let ufo = [0xA2, 0xCD, 0x69, 0x38];
+        let data = zip(&[("games/", &[]), ("UFO", &ufo), ("PONG", &[0x6A, 0x02])]);
+
+        assert_eq!(read(&data, "roms.zip", "UFO"), Ok(ufo.to_vec()));
+        assert_eq!(read(&data, "roms.zip", "PONG"), Ok(vec![0x6A, 0x02]));
+        assert_eq!(
+            list(&data, "roms.zip"),
+            Ok(vec![String::from("UFO"), String::from("PONG")])
+        );
+    }
+
+    #[test]
+    fn missing_entry_is_named_in_the_error() {
+        let data = zip(&[("UFO", &[0x00, 0xE0])]);
+        assert_eq!(
+            read(&data, "roms.zip", "BRIX"),
+            Err(EmulateError::MissingEntry(
+                String::from("roms.zip"),
+                String::from("BRIX")
+            ))
+        );
+    }
+
+    #[test]
+    fn compressed_and_broken_archives_are_refused() {
+        let mut data = zip(&[("UFO", &[0x00, 0xE0])]);
+        assert_eq!(
+            read(&data[..20], "roms.zip", "UFO"),
+            Err(EmulateError::InvalidArchive(String::from("roms.zip")))
+        );
+
+        // Method field of the directory entry, set to DEFLATE
+        let method = data.len() - 22 - 49 + 10;
+        data[method] = 8;
+        assert_eq!(
+            read(&data, "roms.zip", "UFO"),
+            Err(EmulateError::CompressedEntry(
+                String::from("roms.zip"),
+                String::from("UFO")
+            ))
+        );
+    }
+}
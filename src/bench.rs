@@ -0,0 +1,88 @@
+use crate::display;
+use crate::error::EmulateError;
+use crate::memory;
+use crate::{Config, CPU};
+use std::time::Instant;
+
+// Sprite drawn by the benchmark, 15 rows of alternating pixels so every
+// draw touches a full 8x15 area
+const SPRITE: [u8; 15] = [
+    0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA,
+];
+
+// Runs `draws` DXYN instructions straight through emulate_cycle, with no
+// window or rendering involved. Sprites are drawn in pairs at the same
+// position, the second erasing the first, and the position moves between
+// pairs so some wrap at the display edges. Reports the rate and a hash of
+// the final display.
+pub fn run(draws: u64) -> Result<String, EmulateError> {
+    // D01F draws 15 rows at (V0, V1) from I, which points at the sprite
+    // stored after the instruction
+    let mut rom = vec![0xD0, 0x1F];
+    rom.extend_from_slice(&SPRITE);
+    let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &Config::default())?;
+
+    let start = Instant::now();
+    for draw in 0..draws {
+        let pair = draw / 2;
+        cpu.V[0] = (pair * 7 % 64) as u8;
+        cpu.V[1] = (pair * 3 % 32) as u8;
+        cpu.I = memory::ROM_START as u16 + 2;
+        cpu.pc = memory::ROM_START as u16;
+        cpu.emulate_cycle()?;
+    }
+    let elapsed = start.elapsed();
+
+    let rate = draws as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    Ok(format!(
+        "{} draws in {:.3}s, {:.0} sprites/second\nFinal display hash: {:016X}\n",
+        draws,
+        elapsed.as_secs_f64(),
+        rate,
+        display::checksum(&cpu.pack_display())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hash line of a benchmark report
+    fn final_hash(report: &str) -> &str {
+        report.lines().nth(1).unwrap()
+    }
+
+    // Hash line for a 64x32 display with the given pixels lit
+    fn hash_of(lit: &[(usize, usize)]) -> String {
+        let mut packed = vec![0u8; 64 * 32 / 8];
+        for (x, y) in lit {
+            let index = x + y * 64;
+            packed[index / 8] |= 0x80 >> (index % 8);
+        }
+        format!("Final display hash: {:016X}", display::checksum(&packed))
+    }
+
+    #[test]
+    fn pairs_of_draws_leave_a_blank_display() {
+        let report = run(1000).unwrap();
+        assert!(report.starts_with("1000 draws in "));
+        assert_eq!(final_hash(&report), hash_of(&[]));
+    }
+
+    #[test]
+    fn odd_draw_count_leaves_the_last_sprite() {
+        // The third draw starts the second pair, at (7, 3)
+        let mut lit = Vec::new();
+        for (row, byte) in SPRITE.iter().enumerate() {
+            for col in 0..8 {
+                if byte & (0x80 >> col) != 0 {
+                    lit.push((7 + col, 3 + row));
+                }
+            }
+        }
+
+        let report = run(3).unwrap();
+        assert!(report.starts_with("3 draws in "));
+        assert_eq!(final_hash(&report), hash_of(&lit));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::options::{parse_int, parse_register};
+
+// Commands accepted at the step mode prompt
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // Execute a single instruction
+    Step,
+    // Execute a full frame of instructions and tick the timers
+    Frame,
+    // Write a byte to memory: set ADDR VAL
+    Set(usize, u8),
+    // Hex dump a region of memory: mem ADDR LEN
+    Mem(usize, usize),
+    // Zero V registers FIRST to LAST inclusive: clearregs [FIRST LAST]
+    ClearRegs(usize, usize),
+    // List every quirk and whether it's on: quirk
+    Quirks,
+    // Turn a quirk on or off from the next instruction: quirk NAME on|off
+    Quirk(String, bool),
+    // Stop before the instruction at an address is run: break ADDR
+    Break(u16),
+    // Run at full speed until a breakpoint, a halt or an error
+    Continue,
+    Quit,
+}
+
+// Debugger state kept between step mode commands
+#[derive(Default)]
+pub struct Debugger {
+    // Addresses to stop at before running the instruction there
+    pub breakpoints: Vec<u16>,
+
+    // Set by continue until the run stops again
+    pub running: bool,
+}
+
+// Turns the state of the window's step key into single steps, one for
+// each time the key goes down
+#[derive(Default)]
+pub struct Stepper {
+    held: bool,
+}
+
+impl Stepper {
+    // Whether to step, given whether the key is down this frame
+    pub fn update(&mut self, down: bool) -> bool {
+        let step = down && !self.held;
+        self.held = down;
+        step
+    }
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [] | ["s"] | ["step"] => Ok(Command::Step),
+        ["f"] | ["frame"] => Ok(Command::Frame),
+        ["set", addr, val] => {
+            let val = int(val)?;
+            if val > 0xFF {
+                return Err(format!("Value doesn't fit in a byte: {:#X}", val));
+            }
+            Ok(Command::Set(int(addr)? as usize, val as u8))
+        }
+        ["mem", addr, len] => Ok(Command::Mem(int(addr)? as usize, int(len)? as usize)),
+        ["clearregs"] => Ok(Command::ClearRegs(0x0, 0xF)),
+        ["clearregs", first, last] => {
+            let (first, last) = (parse_register(first)?, parse_register(last)?);
+            if first > last {
+                return Err(format!("Empty register range: V{:X}-V{:X}", first, last));
+            }
+            Ok(Command::ClearRegs(first, last))
+        }
+        ["quirk"] => Ok(Command::Quirks),
+        ["quirk", name, state] => {
+            let on = match *state {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("Expected on or off: {}", state)),
+            };
+            Ok(Command::Quirk(name.to_string(), on))
+        }
+        ["b", addr] | ["break", addr] => match int(addr)? {
+            addr @ 0..=0xFFFF => Ok(Command::Break(addr as u16)),
+            addr => Err(format!("Address out of range: {:#X}", addr)),
+        },
+        ["c"] | ["continue"] => Ok(Command::Continue),
+        ["q"] | ["quit"] => Ok(Command::Quit),
+        _ => Err(format!("Unknown command: {}", line.trim())),
+    }
+}
+
+fn int(word: &str) -> Result<u32, String> {
+    parse_int(word).ok_or_else(|| format!("Invalid number: {}", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_memory_commands() {
+        assert_eq!(parse("set 0x300 0xAB"), Ok(Command::Set(0x300, 0xAB)));
+        assert_eq!(parse("mem 0x200 16"), Ok(Command::Mem(0x200, 16)));
+        assert!(parse("set 0x300 0x100").is_err());
+        assert!(parse("set 0x300").is_err());
+    }
+
+    #[test]
+    fn stepper_steps_once_per_press() {
+        let mut stepper = Stepper::default();
+        let steps: Vec<bool> = [false, true, true, false, true]
+            .iter()
+            .map(|down| stepper.update(*down))
+            .collect();
+        assert_eq!(steps, vec![false, true, false, false, true]);
+    }
+
+    #[test]
+    fn parses_clearregs() {
+        assert_eq!(parse("clearregs"), Ok(Command::ClearRegs(0x0, 0xF)));
+        assert_eq!(parse("clearregs V2 VA"), Ok(Command::ClearRegs(0x2, 0xA)));
+        assert!(parse("clearregs 4 2").is_err());
+        assert!(parse("clearregs 0 G").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+// Address ROMs are loaded at
+const START: usize = 0x200;
+
+// Assembly mnemonic for an opcode, in the style of Cowgod's Chip-8
+// technical reference. Operands are hex without a prefix. Opcodes the
+// interpreter doesn't know are shown as a DW data word.
+pub fn mnemonic(opcode: u16) -> String {
+    let X = (opcode & 0x0F00) >> 8;
+    let Y = (opcode & 0x00F0) >> 4;
+    let N = opcode & 0x000F;
+    let NN = opcode & 0x00FF;
+    let NNN = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => String::from("CLS"),
+            0x00EE => String::from("RET"),
+            0x00FB => String::from("SCR"),
+            0x00FC => String::from("SCL"),
+            0x00FE => String::from("LOW"),
+            0x00FF => String::from("HIGH"),
+            _ if opcode & 0xFFF0 == 0x00C0 => format!("SCD {:X}", N),
+            _ => format!("SYS {:03X}", NNN),
+        },
+        0x1000 => format!("JP {:03X}", NNN),
+        0x2000 => format!("CALL {:03X}", NNN),
+        0x3000 => format!("SE V{:X}, {:02X}", X, NN),
+        0x4000 => format!("SNE V{:X}, {:02X}", X, NN),
+        0x5000 if N == 0 => format!("SE V{:X}, V{:X}", X, Y),
+        0x6000 => format!("LD V{:X}, {:02X}", X, NN),
+        0x7000 => format!("ADD V{:X}, {:02X}", X, NN),
+        0x8000 => {
+            let op = match N {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return format!("DW {:04X}", opcode),
+            };
+            format!("{} V{:X}, V{:X}", op, X, Y)
+        }
+        0x9000 if N == 0 => format!("SNE V{:X}, V{:X}", X, Y),
+        0xA000 => format!("LD I, {:03X}", NNN),
+        0xB000 => format!("JP V0, {:03X}", NNN),
+        0xC000 => format!("RND V{:X}, {:02X}", X, NN),
+        0xD000 => format!("DRW V{:X}, V{:X}, {:X}", X, Y, N),
+        0xE000 if NN == 0x9E => format!("SKP V{:X}", X),
+        0xE000 if NN == 0xA1 => format!("SKNP V{:X}", X),
+        0xF000 => match NN {
+            0x07 => format!("LD V{:X}, DT", X),
+            0x0A => format!("LD V{:X}, K", X),
+            0x15 => format!("LD DT, V{:X}", X),
+            0x18 => format!("LD ST, V{:X}", X),
+            0x1E => format!("ADD I, V{:X}", X),
+            0x29 => format!("LD F, V{:X}", X),
+            0x33 => format!("LD B, V{:X}", X),
+            0x55 => format!("LD [I], V{:X}", X),
+            0x65 => format!("LD V{:X}, [I]", X),
+            _ => format!("DW {:04X}", opcode),
+        },
+        _ => format!("DW {:04X}", opcode),
+    }
+}
+
+// Listing of a whole ROM, one instruction per line with its address and
+// opcode. Addresses that a 1NNN, 2NNN or BNNN jumps to get an L_NNN label,
+// and those instructions use the label as their operand. A trailing odd
+// byte is shown as a DB data byte. Only addresses in `range` are listed,
+// though labels still account for jumps from anywhere in the ROM.
+pub fn disassemble(rom: &[u8], range: Range<usize>) -> String {
+    let words = decode(rom);
+
+    // First pass finds the jump targets that land on a listed instruction
+    let labels: BTreeSet<usize> = words
+        .iter()
+        .filter_map(|(_, opcode, _)| target(*opcode))
+        .filter(|addr| words.iter().any(|(listed, _, _)| listed == addr))
+        .collect();
+
+    let mut listing = String::new();
+    for (addr, opcode, long) in words.iter().filter(|(addr, _, _)| range.contains(addr)) {
+        if labels.contains(addr) {
+            listing.push_str(&format!("{}:\n", label(*addr)));
+        }
+
+        if let Some(nnnn) = long {
+            listing.push_str(&format!(
+                "    {:03X}  {:04X}  LD I, {:04X}\n",
+                addr, opcode, nnnn
+            ));
+            continue;
+        }
+
+        let text = match target(*opcode) {
+            Some(target) if labels.contains(&target) => {
+                let op = match opcode & 0xF000 {
+                    0x1000 => "JP",
+                    0x2000 => "CALL",
+                    _ => "JP V0,",
+                };
+                format!("{} {}", op, label(target))
+            }
+            _ => mnemonic(*opcode),
+        };
+        listing.push_str(&format!("    {:03X}  {:04X}  {}\n", addr, opcode, text));
+    }
+
+    let last = START + rom.len() - 1;
+    if rom.len() % 2 == 1 && range.contains(&last) {
+        let addr = last;
+        let byte = rom[rom.len() - 1];
+        listing.push_str(&format!(
+            "    {:03X}  {:02X}    DB {:02X}\n",
+            addr, byte, byte
+        ));
+    }
+
+    listing
+}
+
+// Instructions in a ROM along with their addresses. F000 NNNN takes up
+// four bytes, its NNNN word is given separately and isn't decoded as an
+// instruction of its own.
+fn decode(rom: &[u8]) -> Vec<(usize, u16, Option<u16>)> {
+    let mut words = Vec::new();
+    let mut i = 0;
+
+    while i + 1 < rom.len() {
+        let opcode = (rom[i] as u16) << 8 | rom[i + 1] as u16;
+        if opcode == 0xF000 && i + 3 < rom.len() {
+            let nnnn = (rom[i + 2] as u16) << 8 | rom[i + 3] as u16;
+            words.push((START + i, opcode, Some(nnnn)));
+            i += 4;
+        } else {
+            words.push((START + i, opcode, None));
+            i += 2;
+        }
+    }
+
+    words
+}
+
+// Addresses covered by a ROM once loaded
+pub fn rom_range(rom: &[u8]) -> Range<usize> {
+    START..START + rom.len()
+}
+
+// Address jumped to by 1NNN, called by 2NNN or jumped from by BNNN
+fn target(opcode: u16) -> Option<usize> {
+    match opcode & 0xF000 {
+        0x1000 | 0x2000 | 0xB000 => Some((opcode & 0x0FFF) as usize),
+        _ => None,
+    }
+}
+
+fn label(addr: usize) -> String {
+    format!("L_{:03X}", addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_jump_target_gets_a_label() {
+        let rom = [0x12, 0x04, 0x00, 0xE0, 0x60, 0x01];
+        let listing = disassemble(&rom, rom_range(&rom));
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "    200  1204  JP L_204",
+                "    202  00E0  CLS",
+                "L_204:",
+                "    204  6001  LD V0, 01",
+            ]
+        );
+    }
+
+    #[test]
+    fn range_lists_only_its_instructions() {
+        let rom = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04];
+        let listing = disassemble(&rom, 0x202..0x206);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            ["    202  6102  LD V1, 02", "    204  6203  LD V2, 03"]
+        );
+    }
+
+    #[test]
+    fn long_load_keeps_the_next_instruction_aligned() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x60, 0x01];
+        let listing = disassemble(&rom, rom_range(&rom));
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            ["    200  F000  LD I, 1234", "    204  6001  LD V0, 01"]
+        );
+    }
+
+    #[test]
+    fn jump_to_a_long_load_gets_a_label() {
+        let rom = [0x12, 0x02, 0xF0, 0x00, 0x12, 0x34];
+        let listing = disassemble(&rom, rom_range(&rom));
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "    200  1202  JP L_202",
+                "L_202:",
+                "    202  F000  LD I, 1234",
+            ]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use crate::timing;
+use minifb::Scale;
+use std::path::Path;
+use std::time::Duration;
+
+// Output colors indexed by pixel value. XO-CHIP's two bit planes give
+// four possible values, plain Chip 8 only uses the first two.
+pub const DEFAULT_PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xFF0000, 0x0000FF];
+
+// How DXYN combines each sprite bit with the pixel under it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DrawMode {
+    // Flips pixels, the only mode that reports collisions in VF
+    #[default]
+    Xor,
+    // Turns pixels on
+    Or,
+    // Keeps only pixels that are also set in the sprite
+    And,
+    // Turns pixels off
+    Subtract,
+}
+
+impl DrawMode {
+    // New value of a pixel drawn over with a sprite bit
+    pub fn apply(self, pixel: u32, bit: bool) -> u32 {
+        match self {
+            DrawMode::Xor => pixel ^ bit as u32,
+            DrawMode::Or => pixel | bit as u32,
+            DrawMode::And => pixel & bit as u32,
+            DrawMode::Subtract => pixel & !bit as u32,
+        }
+    }
+}
+
+// Simulates phosphor persistence. Lit pixels jump to full intensity while
+// unlit ones decay toward off over `frames` frames, which hides the flicker
+// from sprites being erased and redrawn.
+pub fn fade(intensity: &mut [u8], gfx: &[u32], frames: u32) {
+    let step = fade_step(frames);
+
+    for (level, pixel) in intensity.iter_mut().zip(gfx.iter()) {
+        if *pixel == 1 {
+            *level = 0xFF;
+        } else {
+            *level = level.saturating_sub(step);
+        }
+    }
+}
+
+// Amount an unlit pixel's intensity drops each frame
+pub fn fade_step(frames: u32) -> u8 {
+    if frames == 0 {
+        return 0xFF;
+    }
+    0xFF_u32.div_ceil(frames) as u8
+}
+
+// Blends between the off and on colors by intensity
+pub fn shade(level: u8, off: u32, on: u32) -> u32 {
+    let level = level as u32;
+    let mut color = 0;
+
+    for shift in [16, 8, 0].iter() {
+        let off = (off >> shift) & 0xFF;
+        let on = (on >> shift) & 0xFF;
+        let channel = (off * (0xFF - level) + on * level) / 0xFF;
+        color |= channel << shift;
+    }
+
+    color
+}
+
+// Color for a pixel value. Inverting swaps the off and on colors.
+pub fn color(pixel: u32, palette: &[u32; 4], invert: bool) -> u32 {
+    let mut index = (pixel & 0b11) as usize;
+    if invert && index < 2 {
+        index ^= 1;
+    }
+    palette[index]
+}
+
+// Text rendering of the display, '#' for lit pixels and '.' for unlit
+pub fn frame_to_string(gfx: &[u32], width: usize) -> String {
+    let mut frame = String::with_capacity(gfx.len() + gfx.len() / width);
+
+    for row in gfx.chunks(width) {
+        for pixel in row {
+            frame.push(if *pixel == 1 { '#' } else { '.' });
+        }
+        frame.push('\n');
+    }
+
+    frame
+}
+
+// FNV-1a hash of a display packed by pack_display, identical displays
+// always hash the same
+pub fn checksum(packed: &[u8]) -> u64 {
+    packed.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+// Text rendering of a sprite, one byte per row, drawing the leftmost
+// `width` pixels of each
+pub fn sprite_to_string(sprite: &[u8], width: usize) -> String {
+    let mut text = String::new();
+
+    for row in sprite {
+        for bit in 0..width {
+            text.push(if row & (0x80 >> bit) != 0 { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+// Whether the given frame is drawn when only every frameskip-th frame is,
+// 0 and 1 both draw every frame
+pub fn should_render(frame: u64, frameskip: u32) -> bool {
+    frameskip <= 1 || frame.is_multiple_of(frameskip as u64)
+}
+
+// Shortest time between window updates, which is as often as frames can be
+// drawn. Without a draw rate the window runs at about 60 Hz.
+pub fn update_interval(draw_rate: Option<u32>) -> Duration {
+    match draw_rate {
+        Some(rate) => timing::cycle_delay(rate),
+        None => Duration::from_micros(16600),
+    }
+}
+
+// Monitor size windows are kept within. minifb 0.19 has no way to ask for
+// the size of the monitor, so this is a common resolution rather than the
+// real one.
+pub const ASSUMED_MONITOR: (usize, usize) = (1920, 1080);
+
+// Largest factor up to the requested one that keeps a width by height
+// display within the monitor, never less than 1. Warns when it had to
+// shrink.
+pub fn clamp_scale(
+    requested: u32,
+    width: usize,
+    height: usize,
+    monitor: (usize, usize),
+) -> (u32, Option<String>) {
+    let fits = (monitor.0 / width).min(monitor.1 / height).max(1) as u32;
+    if requested <= fits {
+        return (requested, None);
+    }
+
+    let warning = format!(
+        "Scale {} doesn't fit a {}x{} monitor, using {}",
+        requested, monitor.0, monitor.1, fits
+    );
+    (fits, Some(warning))
+}
+
+// Window scale for a requested integer factor. minifb only supports power
+// of two factors up to 32, so others are rounded down. Anything larger
+// would make a window bigger than any monitor, so minifb is asked to fit
+// the window to the screen instead.
+pub fn window_scale(factor: u32) -> (Scale, Option<String>) {
+    let scale = match factor {
+        0 | 1 => Scale::X1,
+        2..=3 => Scale::X2,
+        4..=7 => Scale::X4,
+        8..=15 => Scale::X8,
+        16..=31 => Scale::X16,
+        32 => Scale::X32,
+        _ => {
+            let warning = format!("Scale {} is too large, fitting to screen", factor);
+            return (Scale::FitScreen, Some(warning));
+        }
+    };
+
+    let warning = if factor.is_power_of_two() {
+        None
+    } else {
+        Some(format!(
+            "Scale {} is not a power of two, rounding down",
+            factor
+        ))
+    };
+
+    (scale, warning)
+}
+
+// Window title showing the running ROM's file name and frame rate, plus
+// the instructions executed per frame when cycles_per_frame is given and
+// whether the ROM is waiting for a key, ending with the key that quits
+pub fn window_title(
+    title: &str,
+    rom: &str,
+    fps: u32,
+    cycles_per_frame: Option<u64>,
+    waiting: bool,
+    quit_key: &str,
+) -> String {
+    let name = Path::new(rom)
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_else(|| rom.into());
+
+    let mut full = format!("{} - {} - {} FPS", title, name, fps);
+    if let Some(cycles) = cycles_per_frame {
+        full.push_str(&format!(" - {} cycles/frame", cycles));
+    }
+    if waiting {
+        full.push_str(" - Waiting for key");
+    }
+    full.push_str(&format!(" - Press {} to exit", quit_key));
+
+    full
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_is_clamped_to_the_monitor() {
+        assert_eq!(clamp_scale(10, 64, 32, (1920, 1080)), (10, None));
+        assert_eq!(clamp_scale(30, 64, 32, (1920, 1080)), (30, None));
+
+        let (scale, warning) = clamp_scale(100, 64, 32, (1920, 1080));
+        assert_eq!(scale, 30);
+        assert!(warning.is_some());
+
+        // Height is the limit on a narrow monitor
+        assert_eq!(clamp_scale(100, 128, 64, (1280, 320)).0, 5);
+
+        // A display larger than the monitor still gets a window
+        assert_eq!(clamp_scale(4, 128, 64, (100, 50)).0, 1);
+    }
+
+    #[test]
+    fn draw_rate_bounds_window_updates() {
+        assert_eq!(update_interval(None), Duration::from_micros(16600));
+        assert_eq!(update_interval(Some(60)), Duration::from_secs(1) / 60);
+        assert!(update_interval(Some(60)) > update_interval(None));
+    }
+
+    #[test]
+    fn title_names_the_quit_key() {
+        assert_eq!(
+            window_title("Chip-8", "roms/PONG", 60, None, false, "Escape"),
+            "Chip-8 - PONG - 60 FPS - Press Escape to exit"
+        );
+        assert_eq!(
+            window_title("Chip-8", "PONG", 59, Some(10), true, "Q"),
+            "Chip-8 - PONG - 59 FPS - 10 cycles/frame - Waiting for key - Press Q to exit"
+        );
+    }
+
+    #[test]
+    fn title_shows_the_rom_file_name_and_stats() {
+        assert_eq!(
+            window_title(
+                "My Emulator",
+                "/home/me/c8games/TETRIS",
+                30,
+                Some(7),
+                false,
+                "Escape"
+            ),
+            "My Emulator - TETRIS - 30 FPS - 7 cycles/frame - Press Escape to exit"
+        );
+    }
+
+    #[test]
+    fn unlit_pixels_fade_one_step_a_frame() {
+        let gfx = [1, 0];
+        let mut intensity = [0, 0];
+        fade(&mut intensity, &gfx, 5);
+        assert_eq!(intensity, [0xFF, 0]);
+
+        // 255 / 5 frames is a step of 51
+        let gfx = [0, 0];
+        for expected in [204, 153, 102, 51, 0, 0].iter() {
+            fade(&mut intensity, &gfx, 5);
+            assert_eq!(intensity[0], *expected);
+        }
+    }
+
+    #[test]
+    fn no_fade_turns_pixels_straight_off() {
+        let mut intensity = [0xFF];
+        fade(&mut intensity, &[0], 0);
+        assert_eq!(intensity, [0]);
+    }
+
+    #[test]
+    fn inverting_swaps_the_off_and_on_colors() {
+        let palette = [0x000000, 0xFFFFFF, 0xFF0000, 0x0000FF];
+        assert_eq!(color(0, &palette, true), 0xFFFFFF);
+        assert_eq!(color(1, &palette, true), 0x000000);
+
+        // The extra XO-CHIP colors are left alone
+        assert_eq!(color(2, &palette, true), 0xFF0000);
+        assert_eq!(color(3, &palette, true), 0x0000FF);
+    }
+
+    #[test]
+    fn frameskip_renders_every_nth_frame() {
+        let drawn: Vec<u64> = (0..10).filter(|frame| should_render(*frame, 3)).collect();
+        assert_eq!(drawn, vec![0, 3, 6, 9]);
+
+        // 0 and 1 both draw every frame
+        assert!((0..10).all(|frame| should_render(frame, 0)));
+        assert!((0..10).all(|frame| should_render(frame, 1)));
+    }
+}
@@ -0,0 +1,337 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// What happens on reaching an opcode the interpreter doesn't implement
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnknownOpcodePolicy {
+    // Stop with EmulateError::UnknownOpcode
+    #[default]
+    Error,
+    // Stay on the opcode as if the ROM had jumped to itself
+    Halt,
+    // Log it and carry on with the next instruction
+    Skip,
+}
+
+// What happens when a ROM writes below 0x200, where the interpreter and
+// fontset live
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProtectedWritePolicy {
+    // Write it like any other address
+    #[default]
+    Allow,
+    // Stop with EmulateError::ProtectedWrite
+    Error,
+    // Log it and leave memory as it was
+    Ignore,
+}
+
+// Errors raised while executing an instruction
+#[derive(Debug, PartialEq)]
+pub enum EmulateError {
+    // Memory access outside of the address space
+    OutOfBounds(usize),
+
+    // Write to memory reserved for the interpreter
+    ProtectedWrite(usize),
+
+    // Token in a hex ROM that isn't a two digit hex byte
+    InvalidHex(String),
+
+    // ROM that doesn't fit in memory, with its size and the most that fits
+    RomTooLarge(usize, usize),
+
+    // Subroutine call with every stack entry in use
+    StackOverflow,
+
+    // Return from a subroutine with nothing on the stack
+    StackUnderflow,
+
+    // Opcode the interpreter doesn't implement, with the pc it was at
+    UnknownOpcode(u16, u16),
+
+    // Opcode whose behavior depends on a quirk that wasn't chosen, in
+    // strict mode. Holds the pc and opcode.
+    AmbiguousOpcode(u16, u16),
+
+    // Headless run didn't halt within the instruction budget
+    CycleLimitExceeded(u64),
+
+    // Display didn't hash to the expected value at the end of a replay.
+    // Holds the expected and actual hashes.
+    ReplayMismatch(u64, u64),
+
+    // Headless run ended without the display reaching the hash it was
+    // waiting for
+    HashNotReached(u64),
+
+    // Run didn't follow a reference trace. Holds the cycle and the expected
+    // and actual trace lines.
+    TraceDiverged(u64, String, String),
+
+    // Replay script with a line that can't be parsed
+    InvalidReplay(String),
+
+    // V register outside of V0 to VF
+    InvalidRegister(usize),
+
+    // Keypad key outside of 0 to F
+    InvalidKey(usize),
+
+    // Packed display of the wrong length for the current resolution
+    InvalidDisplayData(usize),
+
+    // Saved state file that is missing a field or can't be parsed
+    InvalidState(String),
+
+    // Mode that runs a ROM was started without one
+    MissingRom,
+
+    // Directory to pick a ROM from that has none in it
+    NoRoms(String),
+
+    // Zip that can't be read, with its path
+    #[cfg(feature = "zip")]
+    InvalidArchive(String),
+
+    // Entry that isn't in the zip. Holds the archive and entry names.
+    #[cfg(feature = "zip")]
+    MissingEntry(String, String),
+
+    // Entry that was compressed rather than stored
+    #[cfg(feature = "zip")]
+    CompressedEntry(String, String),
+
+    // Command line flag or sidecar setting that can't be used. The message
+    // is shown as it is.
+    InvalidOptions(String),
+
+    // Log file that can't be created
+    Logger(String),
+
+    // Reading a ROM or writing an output file failed
+    Io(String),
+
+    // Drawing to the window failed, even after opening a new one
+    Window(String),
+}
+
+impl fmt::Display for EmulateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulateError::OutOfBounds(addr) => {
+                write!(f, "Memory access out of bounds at {:#05X}", addr)
+            }
+            EmulateError::ProtectedWrite(addr) => {
+                write!(f, "Write to interpreter memory at {:#05X}", addr)
+            }
+            EmulateError::InvalidHex(token) => {
+                write!(
+                    f,
+                    "Invalid byte in hex ROM, expected two hex digits: {}",
+                    token
+                )
+            }
+            EmulateError::RomTooLarge(size, max) => write!(
+                f,
+                "ROM of {} bytes doesn't fit in memory, at most {} bytes can be loaded",
+                size, max
+            ),
+            EmulateError::StackOverflow => write!(f, "Stack overflow"),
+            EmulateError::StackUnderflow => write!(f, "Return with an empty stack"),
+            EmulateError::UnknownOpcode(pc, opcode) => {
+                write!(f, "Unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            EmulateError::AmbiguousOpcode(pc, opcode) => write!(
+                f,
+                "Opcode {:04X} at {:#05X} depends on a quirk that wasn't chosen",
+                opcode, pc
+            ),
+            EmulateError::ReplayMismatch(expected, actual) => write!(
+                f,
+                "Replay ended with display hash {:016X}, expected {:016X}",
+                actual, expected
+            ),
+            EmulateError::CycleLimitExceeded(limit) => {
+                write!(f, "ROM didn't halt within {} instructions", limit)
+            }
+            EmulateError::HashNotReached(hash) => {
+                write!(f, "Display never hashed to {:016X}", hash)
+            }
+            EmulateError::TraceDiverged(cycle, expected, actual) => write!(
+                f,
+                "Trace diverged at cycle {}\nExpected: {}\nActual:   {}",
+                cycle, expected, actual
+            ),
+            EmulateError::InvalidReplay(e) => write!(f, "Invalid replay script: {}", e),
+            EmulateError::InvalidRegister(reg) => {
+                write!(f, "Invalid register {}, expected V0 to VF", reg)
+            }
+            EmulateError::InvalidKey(key) => write!(f, "Invalid key {}, expected 0 to F", key),
+            EmulateError::InvalidDisplayData(len) => write!(
+                f,
+                "Packed display of {} bytes doesn't match the resolution",
+                len
+            ),
+            EmulateError::InvalidState(e) => write!(f, "Invalid state file: {}", e),
+            EmulateError::MissingRom => write!(f, "No ROM given"),
+            EmulateError::NoRoms(dir) => write!(f, "No ROMs found in {}", dir),
+            #[cfg(feature = "zip")]
+            EmulateError::InvalidArchive(path) => write!(f, "Invalid zip archive: {}", path),
+            #[cfg(feature = "zip")]
+            EmulateError::MissingEntry(archive, name) => {
+                write!(f, "No entry named {} in {}", name, archive)
+            }
+            #[cfg(feature = "zip")]
+            EmulateError::CompressedEntry(archive, name) => write!(
+                f,
+                "Entry {} in {} is compressed, only stored entries can be loaded",
+                name, archive
+            ),
+            EmulateError::InvalidOptions(e) => write!(f, "{}", e),
+            EmulateError::Logger(e) => write!(f, "Logging error: {}", e),
+            EmulateError::Io(e) => write!(f, "I/O error: {}", e),
+            EmulateError::Window(e) => write!(f, "Window error: {}", e),
+        }
+    }
+}
+
+impl Error for EmulateError {}
+
+impl From<io::Error> for EmulateError {
+    fn from(e: io::Error) -> EmulateError {
+        EmulateError::Io(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_describes_each_variant() {
+        let cases = [
+            (
+                EmulateError::OutOfBounds(0x1000),
+                "Memory access out of bounds at 0x1000",
+            ),
+            (
+                EmulateError::ProtectedWrite(0x50),
+                "Write to interpreter memory at 0x050",
+            ),
+            (
+                EmulateError::InvalidHex(String::from("G1")),
+                "Invalid byte in hex ROM, expected two hex digits: G1",
+            ),
+            (
+                EmulateError::RomTooLarge(4000, 3584),
+                "ROM of 4000 bytes doesn't fit in memory, at most 3584 bytes can be loaded",
+            ),
+            (EmulateError::StackOverflow, "Stack overflow"),
+            (EmulateError::StackUnderflow, "Return with an empty stack"),
+            (
+                EmulateError::UnknownOpcode(0x200, 0x5001),
+                "Unknown opcode 5001 at 0x200",
+            ),
+            (
+                EmulateError::AmbiguousOpcode(0x204, 0x8006),
+                "Opcode 8006 at 0x204 depends on a quirk that wasn't chosen",
+            ),
+            (
+                EmulateError::CycleLimitExceeded(300),
+                "ROM didn't halt within 300 instructions",
+            ),
+            (
+                EmulateError::ReplayMismatch(0xAB, 0xCD),
+                "Replay ended with display hash 00000000000000CD, expected 00000000000000AB",
+            ),
+            (
+                EmulateError::HashNotReached(0xAB),
+                "Display never hashed to 00000000000000AB",
+            ),
+            (
+                EmulateError::TraceDiverged(3, String::from("PC:0204"), String::from("PC:0206")),
+                "Trace diverged at cycle 3\nExpected: PC:0204\nActual:   PC:0206",
+            ),
+            (
+                EmulateError::InvalidReplay(String::from("Invalid key on line 2")),
+                "Invalid replay script: Invalid key on line 2",
+            ),
+            (
+                EmulateError::InvalidRegister(16),
+                "Invalid register 16, expected V0 to VF",
+            ),
+            (
+                EmulateError::InvalidKey(16),
+                "Invalid key 16, expected 0 to F",
+            ),
+            (
+                EmulateError::InvalidDisplayData(3),
+                "Packed display of 3 bytes doesn't match the resolution",
+            ),
+            (
+                EmulateError::InvalidState(String::from("bad or missing \"pc\"")),
+                "Invalid state file: bad or missing \"pc\"",
+            ),
+            (EmulateError::MissingRom, "No ROM given"),
+            (
+                EmulateError::NoRoms(String::from("roms")),
+                "No ROMs found in roms",
+            ),
+            (
+                EmulateError::InvalidOptions(String::from("Unknown option: --fast")),
+                "Unknown option: --fast",
+            ),
+            (
+                EmulateError::Logger(String::from("Unable to create log file out.log")),
+                "Logging error: Unable to create log file out.log",
+            ),
+            (
+                EmulateError::Io(String::from("not found")),
+                "I/O error: not found",
+            ),
+            (
+                EmulateError::Window(String::from("no display")),
+                "Window error: no display",
+            ),
+        ];
+
+        for (error, message) in cases.iter() {
+            assert_eq!(error.to_string(), *message);
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn display_describes_archive_errors() {
+        let archive = || String::from("roms.zip");
+        let cases = [
+            (
+                EmulateError::InvalidArchive(archive()),
+                "Invalid zip archive: roms.zip",
+            ),
+            (
+                EmulateError::MissingEntry(archive(), String::from("UFO")),
+                "No entry named UFO in roms.zip",
+            ),
+            (
+                EmulateError::CompressedEntry(archive(), String::from("UFO")),
+                "Entry UFO in roms.zip is compressed, only stored entries can be loaded",
+            ),
+        ];
+
+        for (error, message) in cases.iter() {
+            assert_eq!(error.to_string(), *message);
+        }
+    }
+
+    #[test]
+    fn io_errors_convert_to_io() {
+        let e = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert_eq!(
+            EmulateError::from(e),
+            EmulateError::Io(String::from("missing"))
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Languages a ROM can be exported to as source code
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Lang {
+    #[default]
+    Rust,
+    C,
+}
+
+// Bytes written on each line of an exported array
+const BYTES_PER_LINE: usize = 12;
+
+// ROM as a constant byte array named ROM, ready to paste into a program
+pub fn byte_array(rom: &[u8], lang: Lang) -> String {
+    let (open, close) = match lang {
+        Lang::Rust => (format!("const ROM: [u8; {}] = [", rom.len()), "];"),
+        Lang::C => (format!("const unsigned char ROM[{}] = {{", rom.len()), "};"),
+    };
+
+    let mut array = open;
+    array.push('\n');
+    for line in rom.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X},", byte)).collect();
+        array.push_str(&format!("    {}\n", bytes.join(" ")));
+    }
+    array.push_str(close);
+    array.push('\n');
+
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_array_holds_every_byte() {
+        let rom: Vec<u8> = (0..14).collect();
+        let array = byte_array(&rom, Lang::Rust);
+        let lines: Vec<&str> = array.lines().collect();
+
+        assert_eq!(lines[0], "const ROM: [u8; 14] = [");
+        assert!(lines[1].starts_with("    0x00, 0x01, 0x02,"));
+        assert_eq!(lines[1].matches("0x").count(), BYTES_PER_LINE);
+        assert_eq!(lines[2], "    0x0C, 0x0D,");
+        assert_eq!(lines[3], "];");
+    }
+
+    #[test]
+    fn c_array_uses_braces() {
+        let array = byte_array(&[0x12, 0x00], Lang::C);
+        assert_eq!(
+            array,
+            "const unsigned char ROM[2] = {\n    0x12, 0x00,\n};\n"
+        );
+    }
+}
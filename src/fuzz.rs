@@ -0,0 +1,115 @@
+use crate::memory;
+use crate::trace::{self, TraceFormat};
+use crate::{Config, CPU};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::panic::{self, AssertUnwindSafe};
+
+// Runs `iterations` random opcodes, each from a random CPU state, checking
+// that every one either runs or fails with an EmulateError rather than
+// panicking. The same seed always gives the same opcodes and states, so a
+// failure can be reproduced with --seed, which defaults to 0 here. Quirks
+// and memory size come from the config.
+pub fn run(config: &Config, iterations: u64) -> (String, bool) {
+    let seed = config.seed.unwrap_or(0);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Random bytes in place of a ROM give sprites and FX65 something to read
+    let rom: Vec<u8> = (0..config.memory_size - memory::ROM_START)
+        .map(|_| rng.gen())
+        .collect();
+    let config = Config {
+        seed: Some(seed),
+        ..config.clone()
+    };
+    let mut cpu = match CPU::from_rom(&rom, vec![0; 64 * 32], &config) {
+        Ok(cpu) => cpu,
+        Err(e) => return (format!("Unable to set up the CPU: {}\n", e), false),
+    };
+
+    let mut errors = 0;
+    for iteration in 0..iterations {
+        randomize(&mut cpu, &mut rng);
+        let opcode: u16 = rng.gen();
+        let pc = cpu.pc as usize;
+        cpu.memory.write(pc, (opcode >> 8) as u8).unwrap();
+        cpu.memory.write(pc + 1, opcode as u8).unwrap();
+
+        let state = trace::format_state(&cpu, TraceFormat::Default);
+        match panic::catch_unwind(AssertUnwindSafe(|| cpu.emulate_cycle())) {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => errors += 1,
+            Err(_) => {
+                return (
+                    format!(
+                        "Opcode {:04X} panicked on iteration {} with seed {}, from\n{}\n",
+                        opcode, iteration, seed, state
+                    ),
+                    false,
+                )
+            }
+        }
+    }
+
+    (
+        format!(
+            "{} opcodes with seed {}, {} returned an error, none panicked\n",
+            iterations, seed, errors
+        ),
+        true,
+    )
+}
+
+// Gives the CPU state an opcode can depend on random values, keeping to
+// states a running program could reach
+fn randomize(cpu: &mut CPU, rng: &mut StdRng) {
+    let size = cpu.memory.size();
+
+    for v in cpu.V.iter_mut() {
+        *v = rng.gen();
+    }
+    cpu.I = rng.gen::<u16>() & cpu.i_mask;
+    cpu.pc = rng.gen_range(memory::ROM_START / 2, size / 2) as u16 * 2;
+    cpu.sp = rng.gen_range(0, cpu.stack.len() + 1) as u8;
+    cpu.delay_timer = rng.gen();
+    cpu.sound_timer = rng.gen();
+    cpu.halted = false;
+
+    // Sometimes leave the display in high resolution mode
+    let hires = rng.gen();
+    if hires != cpu.hires {
+        cpu.set_hires(hires);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    // Seeds the suite runs with. A failure names its seed, rerun it with
+    // --fuzz 5000 --seed SEED to reproduce it.
+    const SEEDS: [u64; 3] = [0, 1, 0xC8];
+
+    fn fuzz(config: Config) {
+        for seed in SEEDS.iter() {
+            let config = Config {
+                seed: Some(*seed),
+                ..config.clone()
+            };
+            let (report, passed) = run(&config, 5000);
+            assert!(passed, "{}", report);
+        }
+    }
+
+    #[test]
+    fn random_opcodes_never_panic() {
+        fuzz(Config::default());
+    }
+
+    #[test]
+    fn random_xochip_opcodes_never_panic() {
+        let args = ["--compat", "xochip"].iter().map(|arg| arg.to_string());
+        fuzz(Options::parse(args).unwrap().config);
+    }
+}
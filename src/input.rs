@@ -0,0 +1,290 @@
+use crate::error::EmulateError;
+use crate::keymap::Keymap;
+use minifb::Window;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Source of hex keypad state, polled once per frame
+pub trait Input {
+    fn poll(&mut self, frame: u64) -> [bool; 16];
+}
+
+// Keys currently held on the physical keyboard
+pub struct WindowInput<'a> {
+    pub window: &'a Window,
+    pub keymap: &'a Keymap,
+}
+
+impl Input for WindowInput<'_> {
+    fn poll(&mut self, _frame: u64) -> [bool; 16] {
+        let mut keys = [false; 16];
+
+        if let Some(held) = self.window.get_keys() {
+            for t in held {
+                if let Some(key) = self.keymap.lookup(t) {
+                    keys[key] = true;
+                }
+            }
+        }
+
+        keys
+    }
+}
+
+// Keys that went down or up between the two most recent polls
+#[derive(Default)]
+pub struct KeyEdges {
+    previous: [bool; 16],
+    current: [bool; 16],
+}
+
+impl KeyEdges {
+    // Takes the key state from the latest poll
+    pub fn update(&mut self, keys: [bool; 16]) {
+        self.previous = self.current;
+        self.current = keys;
+    }
+
+    pub fn pressed_this_frame(&self) -> Vec<usize> {
+        (0..16)
+            .filter(|key| self.current[*key] && !self.previous[*key])
+            .collect()
+    }
+
+    pub fn released_this_frame(&self) -> Vec<usize> {
+        (0..16)
+            .filter(|key| !self.current[*key] && self.previous[*key])
+            .collect()
+    }
+}
+
+// A key going down or up at the start of a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: usize,
+    pub down: bool,
+}
+
+// Written in the replay script format
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = if self.down { "down" } else { "up" };
+        write!(f, "{} {:X} {}", self.frame, self.key, state)
+    }
+}
+
+// Replays key events read from a script, one `frame key_hex down|up` event
+// per line. Blank lines and lines starting with # are ignored.
+#[derive(Default)]
+pub struct ScriptedInput {
+    events: Vec<KeyEvent>,
+
+    // Next event to apply
+    next: usize,
+    keys: [bool; 16],
+}
+
+impl ScriptedInput {
+    pub fn parse(script: &str) -> Result<ScriptedInput, String> {
+        let mut events = Vec::new();
+
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("Invalid replay event on line {}: {}", number + 1, line);
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (frame, key, state) = match words[..] {
+                [frame, key, state] => (frame, key, state),
+                _ => return Err(invalid()),
+            };
+
+            let frame = frame.parse().map_err(|_| invalid())?;
+            let key = match usize::from_str_radix(key.trim_start_matches("0x"), 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(invalid()),
+            };
+            let down = match state {
+                "down" => true,
+                "up" => false,
+                _ => return Err(invalid()),
+            };
+
+            events.push(KeyEvent { frame, key, down });
+        }
+
+        // Scripts may list events out of order, ties keep their line order
+        events.sort_by_key(|event| event.frame);
+
+        Ok(ScriptedInput::new(events))
+    }
+
+    // Frame of the last event, after which the keys never change
+    pub fn end_frame(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.frame)
+    }
+
+    pub fn new(events: Vec<KeyEvent>) -> ScriptedInput {
+        ScriptedInput {
+            events,
+            next: 0,
+            keys: [false; 16],
+        }
+    }
+}
+
+impl Input for ScriptedInput {
+    fn poll(&mut self, frame: u64) -> [bool; 16] {
+        while let Some(event) = self.events.get(self.next) {
+            if event.frame > frame {
+                break;
+            }
+            self.keys[event.key] = event.down;
+            self.next += 1;
+        }
+
+        self.keys
+    }
+}
+
+// Writes each key state change as a replay script event
+pub struct Recorder {
+    out: BufWriter<File>,
+    edges: KeyEdges,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Recorder, EmulateError> {
+        let file = File::create(path)?;
+
+        Ok(Recorder {
+            out: BufWriter::new(file),
+            edges: KeyEdges::default(),
+        })
+    }
+
+    pub fn record(&mut self, frame: u64, keys: &[bool; 16]) -> Result<(), EmulateError> {
+        self.edges.update(*keys);
+
+        let pressed = self
+            .edges
+            .pressed_this_frame()
+            .into_iter()
+            .map(|key| (key, true));
+        let released = self
+            .edges
+            .released_this_frame()
+            .into_iter()
+            .map(|key| (key, false));
+
+        for (key, down) in pressed.chain(released) {
+            let event = KeyEvent { frame, key, down };
+            writeln!(self.out, "{}", event)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), EmulateError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_sets_keys_from_their_frame_on() {
+        let script = "# Press 5, then A and release 5\n\
+                      0 5 down\n\
+                      \n\
+                      10 A down\n\
+                      10 0x5 up\n";
+        let mut input = ScriptedInput::parse(script).unwrap();
+        assert_eq!(input.end_frame(), 10);
+
+        let keys = input.poll(9);
+        assert!(keys[0x5]);
+        assert!(!keys[0xA]);
+
+        let keys = input.poll(10);
+        assert!(!keys[0x5]);
+        assert!(keys[0xA]);
+    }
+
+    #[test]
+    fn script_events_may_be_out_of_order() {
+        let mut input = ScriptedInput::parse("4 1 up\n2 1 down").unwrap();
+        assert!(input.poll(3)[0x1]);
+        assert!(!input.poll(4)[0x1]);
+    }
+
+    #[test]
+    fn malformed_script_lines_are_reported() {
+        for script in ["0 5", "0 10 down", "x 5 down", "0 5 held"].iter() {
+            let error = ScriptedInput::parse(script).err().unwrap();
+            assert_eq!(error, format!("Invalid replay event on line 1: {}", script));
+        }
+    }
+
+    #[test]
+    fn recording_parses_back_to_the_same_events() {
+        let path = std::env::temp_dir().join("rusty-chip8-recording.txt");
+        let path = path.to_str().unwrap();
+
+        let mut keys = [false; 16];
+        let mut recorder = Recorder::create(path).unwrap();
+        recorder.record(0, &keys).unwrap();
+        keys[0x3] = true;
+        recorder.record(1, &keys).unwrap();
+        keys[0x3] = false;
+        keys[0xC] = true;
+        recorder.record(5, &keys).unwrap();
+        recorder.flush().unwrap();
+
+        let script = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(script, "1 3 down\n5 C down\n5 3 up\n");
+
+        let replay = ScriptedInput::parse(&script).unwrap();
+        let down = |frame, key| KeyEvent {
+            frame,
+            key,
+            down: true,
+        };
+        let up = |frame, key| KeyEvent {
+            frame,
+            key,
+            down: false,
+        };
+        assert_eq!(replay.events, vec![down(1, 0x3), down(5, 0xC), up(5, 0x3)]);
+    }
+
+    #[test]
+    fn edges_follow_scripted_key_changes() {
+        let mut input = ScriptedInput::parse("1 2 down\n1 7 down\n3 2 up").unwrap();
+        let mut edges = KeyEdges::default();
+
+        let mut seen = Vec::new();
+        for frame in 0..4 {
+            edges.update(input.poll(frame));
+            seen.push((edges.pressed_this_frame(), edges.released_this_frame()));
+        }
+
+        assert_eq!(
+            seen,
+            vec![
+                (vec![], vec![]),
+                (vec![0x2, 0x7], vec![]),
+                (vec![], vec![]),
+                (vec![], vec![0x2]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use minifb::Key;
+
+// Layout of the hex keypad on the original hardware
+pub const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Layouts that can be picked with --keymap
+pub const LAYOUTS: [&str; 2] = ["grid", "sequential"];
+
+// Physical keyboard key for each Chip 8 hex key
+pub struct Keymap {
+    keys: [Key; 16],
+}
+
+impl Default for Keymap {
+    // Left side of a QWERTY keyboard laid out like the keypad
+    fn default() -> Keymap {
+        Keymap {
+            keys: [
+                Key::X,    // 0
+                Key::Key1, // 1
+                Key::Key2, // 2
+                Key::Key3, // 3
+                Key::Q,    // 4
+                Key::W,    // 5
+                Key::E,    // 6
+                Key::A,    // 7
+                Key::S,    // 8
+                Key::D,    // 9
+                Key::Z,    // A
+                Key::C,    // B
+                Key::Key4, // C
+                Key::R,    // D
+                Key::F,    // E
+                Key::V,    // F
+            ],
+        }
+    }
+}
+
+impl Keymap {
+    // Keymap by its --keymap name. The COSMAC VIP keypad is the grid.
+    pub fn layout(name: &str) -> Option<Keymap> {
+        match name {
+            "grid" | "cosmac" => Some(Keymap::default()),
+            // Rows of the keyboard in hex order, 1234 for 0123 through
+            // ZXCV for CDEF
+            "sequential" => Some(Keymap {
+                keys: [
+                    Key::Key1,
+                    Key::Key2,
+                    Key::Key3,
+                    Key::Key4,
+                    Key::Q,
+                    Key::W,
+                    Key::E,
+                    Key::R,
+                    Key::A,
+                    Key::S,
+                    Key::D,
+                    Key::F,
+                    Key::Z,
+                    Key::X,
+                    Key::C,
+                    Key::V,
+                ],
+            }),
+            _ => None,
+        }
+    }
+
+    // Hex key mapped to a physical key
+    pub fn lookup(&self, key: Key) -> Option<usize> {
+        self.keys.iter().position(|k| *k == key)
+    }
+
+    // Keypad grid showing which physical key drives each hex key
+    pub fn render(&self) -> String {
+        let mut grid = String::new();
+
+        for row in KEYPAD.iter() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|hex| format!("{:X}: {:<4}", hex, key_name(self.keys[*hex])))
+                .collect();
+            grid.push_str(cells.join("  ").trim_end());
+            grid.push('\n');
+        }
+
+        grid
+    }
+}
+
+// Keys that can be named on the command line, such as for --quit-key
+const NAMED_KEYS: [Key; 63] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::Escape,
+    Key::Tab,
+    Key::Space,
+    Key::Enter,
+    Key::Backspace,
+    Key::Delete,
+    Key::Insert,
+    Key::Home,
+    Key::End,
+    Key::Up,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Pause,
+    Key::Backquote,
+];
+
+// Key by the name it's printed with, ignoring case
+pub fn parse_key(name: &str) -> Option<Key> {
+    NAMED_KEYS
+        .iter()
+        .copied()
+        .find(|key| key_name(*key).eq_ignore_ascii_case(name))
+}
+
+// Name of a key as printed on the keyboard
+pub fn key_name(key: Key) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Key") {
+        Some(digit) => digit.to_string(),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_parse_by_their_printed_name() {
+        assert_eq!(parse_key("escape"), Some(Key::Escape));
+        assert_eq!(parse_key("Q"), Some(Key::Q));
+        assert_eq!(parse_key("5"), Some(Key::Key5));
+        assert_eq!(parse_key("Key5"), None);
+        assert_eq!(parse_key("nope"), None);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for key in NAMED_KEYS.iter() {
+            assert_eq!(parse_key(&key_name(*key)), Some(*key));
+        }
+    }
+
+    #[test]
+    fn default_table_lists_all_16_keys() {
+        let table = Keymap::default().render();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "1: 1     2: 2     3: 3     C: 4");
+        assert_eq!(lines[3], "A: Z     0: X     B: C     F: V");
+
+        for hex in 0..16 {
+            assert!(table.contains(&format!("{:X}: ", hex)), "{:X}", hex);
+        }
+    }
+
+    // Keyboard rows from the number row down, left to right
+    const ROWS: [[Key; 4]; 4] = [
+        [Key::Key1, Key::Key2, Key::Key3, Key::Key4],
+        [Key::Q, Key::W, Key::E, Key::R],
+        [Key::A, Key::S, Key::D, Key::F],
+        [Key::Z, Key::X, Key::C, Key::V],
+    ];
+
+    #[test]
+    fn grid_layout_follows_the_keypad() {
+        let keymap = Keymap::layout("grid").unwrap();
+        for (row, keys) in ROWS.iter().enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                assert_eq!(keymap.lookup(*key), Some(KEYPAD[row][col]));
+            }
+        }
+        assert_eq!(Keymap::layout("cosmac").unwrap().keys, keymap.keys);
+    }
+
+    #[test]
+    fn sequential_layout_counts_along_the_rows() {
+        let keymap = Keymap::layout("sequential").unwrap();
+        for (row, keys) in ROWS.iter().enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                assert_eq!(keymap.lookup(*key), Some(row * 4 + col));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_layouts_are_refused() {
+        assert!(Keymap::layout("dvorak").is_none());
+        assert_eq!(Keymap::default().lookup(Key::Escape), None);
+    }
+}
@@ -0,0 +1,86 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::sync::Mutex;
+
+// Sends diagnostics to stderr, or to a file, so they don't get mixed in
+// with what the emulator itself prints
+struct Logger {
+    out: Mutex<Box<dyn Write + Send>>,
+
+    // Lines written to a file are prefixed with their level
+    show_level: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Logging must never take the emulator down, so write errors are
+        // dropped
+        if let Ok(mut out) = self.out.lock() {
+            let _ = if self.show_level {
+                writeln!(out, "{:<5} {}", record.level(), record.args())
+            } else {
+                writeln!(out, "{}", record.args())
+            };
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+// Installs the logger, writing to path when given and stderr otherwise
+pub fn init(path: Option<&str>, level: LevelFilter) -> Result<(), String> {
+    let logger = match path {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("Unable to create log file {}: {}", path, e))?;
+            Logger {
+                out: Mutex::new(Box::new(LineWriter::new(file))),
+                show_level: true,
+            }
+        }
+        None => Logger {
+            out: Mutex::new(Box::new(io::stderr())),
+            show_level: false,
+        },
+    };
+
+    log::set_logger(Box::leak(Box::new(logger))).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::info;
+
+    // The logger can only be installed once per process, so this is the
+    // only test that does
+    #[test]
+    fn messages_go_to_the_log_file() {
+        let path = std::env::temp_dir().join("rusty-chip8-test.log");
+        let path = path.to_str().unwrap();
+
+        init(Some(path), LevelFilter::Info).unwrap();
+        info!("Loaded test ROM");
+        log::logger().flush();
+
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(text.contains("INFO  Loaded test ROM\n"), "{}", text);
+    }
+}
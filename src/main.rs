@@ -1,12 +1,164 @@
 #![allow(non_snake_case)]
 extern crate minifb;
 
-use minifb::{Key, Scale, Window, WindowOptions};
-use rand::thread_rng;
-use rand::Rng;
+#[cfg(feature = "zip")]
+mod archive;
+mod bench;
+mod debugger;
+mod disasm;
+mod display;
+mod error;
+mod export;
+mod fuzz;
+mod input;
+mod keymap;
+mod logger;
+mod memory;
+mod menu;
+mod options;
+mod overlay;
+mod playlist;
+mod profile;
+mod quirk_test;
+mod quirks;
+mod self_test;
+mod state;
+mod theme;
+mod timing;
+mod trace;
+
+use debugger::{Command, Debugger, Stepper};
+use display::DrawMode;
+use error::{EmulateError, ProtectedWritePolicy, UnknownOpcodePolicy};
+use input::{Input, Recorder, ScriptedInput, WindowInput};
+use keymap::Keymap;
+use log::{debug, info, log_enabled, trace, warn, Level};
+use memory::{MemFill, MemoryBus, RamBus};
+use menu::Menu;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use options::Options;
+use playlist::Playlist;
+use quirks::Quirks;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Write};
 use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use timing::{Clock, FrameBudget, MockClock, SystemClock, Throttle};
+use trace::TraceFormat;
+
+// Timers count down at 60 Hz
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Hex digit glyphs, 5 bytes each
+const FONTSET: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// Address the fontset is loaded at
+const FONTSET_START: usize = 0x50;
+
+// Options applied when the CPU is created
+#[derive(Clone, Debug)]
+struct Config {
+    mem_fill: MemFill,
+
+    // (address, value) pairs written after the ROM is loaded
+    pokes: Vec<(usize, u8)>,
+
+    // Allow pokes to overwrite the fontset
+    force_poke: bool,
+
+    // Leave the fontset out for ROMs that bring their own, the region
+    // keeps the memory fill pattern
+    no_fontset: bool,
+
+    // Read ROMs as hex text whatever their file name
+    hex_rom: bool,
+
+    // Start with random values in V0-VF instead of zeros
+    rand_regs: bool,
+
+    // Seed for random memory fill and CXNN, so runs can be repeated
+    seed: Option<u64>,
+
+    quirks: Quirks,
+
+    // Subroutine calls that can be nested
+    stack_depth: usize,
+
+    // Bytes of memory, which limits the size of ROM that can be loaded
+    memory_size: usize,
+
+    // Bits of I that are kept, 12 for classic CHIP-8 and 16 for XO-CHIP
+    i_mask: u16,
+
+    // Quirks that weren't chosen explicitly. Opcodes they affect are
+    // refused, set in strict mode.
+    ambiguous_quirks: Vec<&'static str>,
+
+    // Refuse behavior that real programs shouldn't depend on
+    strict: bool,
+
+    // What to do on reaching an opcode the interpreter doesn't implement
+    on_unknown: UnknownOpcodePolicy,
+
+    // What to do when the ROM writes to the interpreter area
+    protect_interpreter: ProtectedWritePolicy,
+
+    // How DXYN combines sprites with the display
+    draw_mode: DrawMode,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mem_fill: MemFill::default(),
+            pokes: Vec::new(),
+            force_poke: false,
+            no_fontset: false,
+            hex_rom: false,
+            rand_regs: false,
+            seed: None,
+            quirks: Quirks::default(),
+            stack_depth: 16,
+            memory_size: memory::CLASSIC_SIZE,
+            i_mask: 0x0FFF,
+            ambiguous_quirks: Vec::new(),
+            strict: false,
+            on_unknown: UnknownOpcodePolicy::default(),
+            protect_interpreter: ProtectedWritePolicy::default(),
+            draw_mode: DrawMode::default(),
+        }
+    }
+}
+
+impl Config {
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
 
 struct CPU {
     // Chip 8 has 35 opcodes
@@ -14,13 +166,20 @@ struct CPU {
     opcode: u16,
 
     // Chip 8 has 4K memory
-    memory: [u16; 4096],
+    memory: Box<dyn MemoryBus>,
 
     // Graphics buffer
     height: u32,
     width: u32,
     gfx: Vec<u32>,
 
+    // Bounding box (x0, y0, x1, y1), inclusive, of the pixels changed since
+    // the display was last drawn, so only those need recoloring
+    dirty: Option<(u32, u32, u32, u32)>,
+
+    // SCHIP high resolution (128x64) mode
+    hires: bool,
+
     // CPU Registers
     V: [u8; 16],
 
@@ -29,90 +188,398 @@ struct CPU {
     I: u16,
     pc: u16,
 
-    k: u8,
+    // Hex keypad state, true while a key is held down
+    keys: [bool; 16],
+
+    // Key seen pressed by FX0A, waiting for it to be released
+    fx0a_key: Option<u8>,
+
+    // Set while FX0A hasn't completed, so the ROM is blocked on input
+    waiting_for_key: bool,
+
+    // Keys that completed an FX0A and haven't been released since. They
+    // can't complete another until pressed again.
+    fx0a_consumed: [bool; 16],
+
+    // Bits of I that are kept when it's set
+    i_mask: u16,
+
+    quirks: Quirks,
+    ambiguous_quirks: Vec<&'static str>,
+    strict: bool,
+    on_unknown: UnknownOpcodePolicy,
+    protect_interpreter: ProtectedWritePolicy,
+    draw_mode: DrawMode,
 
     // Maintains current location
     // before jumps are performed
-    stack: [u16; 16],
+    stack: Vec<u16>,
     sp: u8,
 
     delay_timer: u8,
     sound_timer: u8,
-}
 
-impl CPU {
-    fn initialize(path: &str, gfx: Vec<u32>) -> CPU {
-        // Loading game file into buffer
-        let mut f = File::open(path).unwrap();
-        let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer);
+    // Elapsed time not yet consumed by a timer tick
+    timer_elapsed: Duration,
 
-        // Initializing and loading memory
-        let mut memory: [u16; 4096] = [0x0000; 4096];
+    // Set once the ROM jumps to itself, which is how most programs end
+    halted: bool,
 
-        for (i, _) in buffer.iter().enumerate() {
-            // println!("pos {}: {:#06x}", i, buffer[i]);
-            memory[i + 512] = buffer[i].into();
-        }
-
-        let chip8_fontset = vec![
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
+    // Number of times each opcode category has been executed
+    profile: Vec<u64>,
 
-        let start_loc = 0x50;
-        for (index, font) in chip8_fontset.iter().enumerate() {
-            memory[start_loc + index] = *font as u16;
-        }
+    // Number of times the instruction at each address has been executed
+    hotspots: Vec<u64>,
+
+    // Instructions spent waiting on FX0A or in a busy-wait loop
+    idle_cycles: u64,
+
+    // First and last address of the busy-wait loop being executed
+    idle_loop: Option<(u16, u16)>,
+
+    // Instructions executed so far
+    cycles: u64,
+
+    // Hash of the loaded ROM, saved with the state to catch mismatches
+    rom_hash: u64,
+
+    // Receives a line of state after every instruction
+    trace: Option<BufWriter<File>>,
+    trace_format: TraceFormat,
 
-        CPU {
+    // Source of CXNN random numbers
+    rng: StdRng,
+}
+
+impl CPU {
+    fn initialize(path: &str, gfx: Vec<u32>, config: &Config) -> Result<CPU, EmulateError> {
+        CPU::from_rom(&CPU::read_rom(path, config)?, gfx, config)
+    }
+
+    // Same as initialize, for a ROM already in memory
+    fn from_rom(rom: &[u8], gfx: Vec<u32>, config: &Config) -> Result<CPU, EmulateError> {
+        let memory = CPU::load_memory(rom, config)?;
+        let mut rng = config.rng();
+
+        Ok(CPU {
             opcode: 0,
-            memory: memory,
+            memory: Box::new(memory),
             height: 32,
             width: 64,
             gfx: gfx,
-            V: [0x0000; 16],
+            dirty: Some((0, 0, 63, 31)),
+            hires: false,
+            V: CPU::initial_registers(config, &mut rng),
             I: 0,
             pc: 0x200,
-            stack: [0x0000; 16],
+            stack: vec![0x0000; config.stack_depth],
             sp: 0,
-            k: 0,
+            keys: [false; 16],
+            fx0a_key: None,
+            waiting_for_key: false,
+            fx0a_consumed: [false; 16],
+            i_mask: config.i_mask,
+            quirks: config.quirks,
+            ambiguous_quirks: config.ambiguous_quirks.clone(),
+            strict: config.strict,
+            on_unknown: config.on_unknown,
+            protect_interpreter: config.protect_interpreter,
+            draw_mode: config.draw_mode,
             delay_timer: 0,
             sound_timer: 0,
+            timer_elapsed: Duration::from_secs(0),
+            halted: false,
+            profile: vec![0; profile::OPCODES.len()],
+            hotspots: vec![0; config.memory_size],
+            idle_cycles: 0,
+            idle_loop: None,
+            cycles: 0,
+            rom_hash: display::checksum(rom),
+            trace: None,
+            trace_format: TraceFormat::Default,
+            rng,
+        })
+    }
+
+    // Zeros, or random bytes with rand_regs so ROMs that forget to set a
+    // register before using it stand out
+    fn initial_registers(config: &Config, rng: &mut StdRng) -> [u8; 16] {
+        if config.rand_regs {
+            rng.gen()
+        } else {
+            [0; 16]
+        }
+    }
+
+    // ROMs are raw binary, unless they're hex text, either because
+    // hex_rom is set or because the file name ends in .hex
+    fn read_rom(path: &str, config: &Config) -> Result<Vec<u8>, EmulateError> {
+        let hex = config.hex_rom || path.to_lowercase().ends_with(".hex");
+
+        // Loading game file into buffer
+        #[cfg(feature = "zip")]
+        let buffer = archive::read_path(path)?;
+        #[cfg(not(feature = "zip"))]
+        let buffer = std::fs::read(path)?;
+
+        if hex {
+            return CPU::parse_hex(&String::from_utf8_lossy(&buffer));
+        }
+        Ok(buffer)
+    }
+
+    // Bytes written as two digit hex values separated by whitespace
+    fn parse_hex(text: &str) -> Result<Vec<u8>, EmulateError> {
+        text.split_whitespace()
+            .map(|token| match u8::from_str_radix(token, 16) {
+                Ok(byte) if token.len() == 2 => Ok(byte),
+                _ => Err(EmulateError::InvalidHex(token.to_string())),
+            })
+            .collect()
+    }
+
+    // Builds memory holding the fontset and the ROM
+    fn load_memory(rom: &[u8], config: &Config) -> Result<RamBus, EmulateError> {
+        // Initializing and loading memory
+        let mut memory = RamBus::new(config.memory_size, config.mem_fill, &mut config.rng());
+
+        let max = memory.size() - memory::ROM_START;
+        if rom.len() > max {
+            return Err(EmulateError::RomTooLarge(rom.len(), max));
+        }
+
+        for (i, byte) in rom.iter().enumerate() {
+            // println!("pos {}: {:#06x}", i, buffer[i]);
+            memory.write(i + memory::ROM_START, *byte).unwrap();
+        }
+
+        if !config.no_fontset {
+            for (index, font) in FONTSET.iter().enumerate() {
+                memory.write(FONTSET_START + index, *font).unwrap();
+            }
+        }
+
+        for (addr, val) in config.pokes.iter() {
+            if CPU::in_fontset(*addr) && !config.force_poke && !config.no_fontset {
+                return Err(EmulateError::ProtectedWrite(*addr));
+            }
+            memory.write(*addr, *val)?;
+        }
+
+        Ok(memory)
+    }
+
+    // Keeps a jump target inside memory
+    fn mask_address(&self, addr: u16) -> u16 {
+        (addr as usize % self.memory.size()) as u16
+    }
+
+    // Sets I, dropping the bits the variant's address register doesn't have
+    fn set_i(&mut self, addr: u16) {
+        self.I = addr & self.i_mask;
+    }
+
+    // Handles an opcode the interpreter doesn't implement
+    fn unknown_opcode(&mut self) -> Result<(), EmulateError> {
+        match self.on_unknown {
+            UnknownOpcodePolicy::Error => Err(EmulateError::UnknownOpcode(self.pc, self.opcode)),
+            // The pc stays put, so this is only reported the first time
+            UnknownOpcodePolicy::Halt => {
+                if !self.halted {
+                    warn!(
+                        "Halting at unknown opcode {:04X} at {:#05X}",
+                        self.opcode, self.pc
+                    );
+                }
+                self.halted = true;
+                Ok(())
+            }
+            UnknownOpcodePolicy::Skip => {
+                warn!(
+                    "Skipping unknown opcode {:04X} at {:#05X}",
+                    self.opcode, self.pc
+                );
+                self.pc = self.pc.wrapping_add(2);
+                Ok(())
+            }
+        }
+    }
+
+    // Byte of sprite data for DXYN. Rows past the end of memory are blank,
+    // as in most modern interpreters, or an error in strict mode. The
+    // COSMAC VIP didn't decode the upper address lines so the read wrapped
+    // around its RAM instead, but no known ROM depends on that.
+    fn sprite_byte(&self, addr: usize) -> Result<u8, EmulateError> {
+        if addr >= self.memory.size() && !self.strict {
+            return Ok(0);
+        }
+        self.memory.read(addr)
+    }
+
+    // Conditional skip shared by the 3XNN, 4XNN, 5XY0, 9XY0, EX9E and EXA1
+    // opcodes: skips the next instruction when cond holds. A four byte
+    // F000 NNNN is skipped as a whole.
+    fn skip_if(&mut self, cond: bool) -> Result<(), EmulateError> {
+        let next = self.pc.wrapping_add(2);
+        self.pc = if !cond {
+            next
+        } else if self.read_word(next as usize)? == 0xF000 {
+            next.wrapping_add(4)
+        } else {
+            next.wrapping_add(2)
+        };
+
+        Ok(())
+    }
+
+    // Two bytes of memory as a big endian word. A word reaching past the
+    // end of memory reads as 0, the same as an empty sprite row.
+    fn read_word(&self, addr: usize) -> Result<u16, EmulateError> {
+        if addr + 1 >= self.memory.size() {
+            return Ok(0);
+        }
+        Ok((self.memory.read(addr)? as u16) << 8 | self.memory.read(addr + 1)? as u16)
+    }
+
+    fn in_fontset(addr: usize) -> bool {
+        (FONTSET_START..FONTSET_START + FONTSET.len()).contains(&addr)
+    }
+
+    // Writes a byte from outside the running program, refusing to touch
+    // the fontset
+    fn set_mem(&mut self, addr: usize, val: u8) -> Result<(), EmulateError> {
+        if CPU::in_fontset(addr) {
+            return Err(EmulateError::ProtectedWrite(addr));
+        }
+        self.memory.write(addr, val)
+    }
+
+    // Writes a byte on behalf of the running program
+    fn write_mem(&mut self, addr: usize, val: u8) -> Result<(), EmulateError> {
+        if addr < memory::ROM_START {
+            match self.protect_interpreter {
+                ProtectedWritePolicy::Allow => {}
+                ProtectedWritePolicy::Error => return Err(EmulateError::ProtectedWrite(addr)),
+                ProtectedWritePolicy::Ignore => {
+                    warn!("Ignoring write to {:#05X} at {:#05X}", addr, self.pc);
+                    return Ok(());
+                }
+            }
+        }
+        self.memory.write(addr, val)
+    }
+
+    // Writes a V register from outside the running program
+    fn set_reg(&mut self, reg: usize, val: u8) -> Result<(), EmulateError> {
+        *self
+            .V
+            .get_mut(reg)
+            .ok_or(EmulateError::InvalidRegister(reg))? = val;
+        Ok(())
+    }
+
+    // Hex dump of len bytes starting at addr, 16 bytes per line
+    fn dump_memory(&self, addr: usize, len: usize) -> Result<String, EmulateError> {
+        let mut dump = String::new();
+
+        for line in (addr..addr + len).step_by(16) {
+            dump.push_str(&format!("{:04X}:", line));
+            for loc in line..(line + 16).min(addr + len) {
+                dump.push_str(&format!(" {:02X}", self.memory.read(loc)?));
+            }
+            dump.push('\n');
         }
+
+        Ok(dump)
+    }
+
+    // Swaps in a different ROM and starts it from the beginning
+    fn load(&mut self, path: &str, config: &Config) -> Result<(), EmulateError> {
+        let rom = CPU::read_rom(path, config)?;
+        self.memory = Box::new(CPU::load_memory(&rom, config)?);
+        self.rom_hash = display::checksum(&rom);
+        self.rng = config.rng();
+        self.reset();
+        self.V = CPU::initial_registers(config, &mut self.rng);
+
+        Ok(())
+    }
+
+    // Puts the CPU back in its power-on state, leaving memory alone
+    fn reset(&mut self) {
+        self.opcode = 0;
+        self.height = 32;
+        self.width = 64;
+        self.gfx = vec![0; 64 * 32];
+        self.mark_all_dirty();
+        self.hires = false;
+        self.V = [0x0000; 16];
+        self.I = 0;
+        self.pc = 0x200;
+        self.stack = vec![0x0000; self.stack.len()];
+        self.sp = 0;
+        self.keys = [false; 16];
+        self.fx0a_key = None;
+        self.waiting_for_key = false;
+        self.fx0a_consumed = [false; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.timer_elapsed = Duration::from_secs(0);
+        self.halted = false;
+        self.profile = vec![0; profile::OPCODES.len()];
+        self.hotspots = vec![0; self.memory.size()];
+        self.idle_cycles = 0;
+        self.idle_loop = None;
+        self.cycles = 0;
     }
 
-    fn emulate_cycle(&mut self) {
+    fn emulate_cycle(&mut self) -> Result<(), EmulateError> {
         // Opcodes are stored in two memory locations
         // We need both to get the full opcode
         let opcode_pt_1 = self.pc as usize;
-        let opcode_pt_2 = (self.pc + 1) as usize;
+        let opcode_pt_2 = opcode_pt_1 + 1;
+
+        // The second byte of an opcode at the very top of memory would be
+        // past the end
+        if opcode_pt_2 >= self.memory.size() {
+            return Err(EmulateError::OutOfBounds(opcode_pt_1));
+        }
 
-        self.opcode = self.memory[opcode_pt_1] << 8 | self.memory[opcode_pt_2];
+        let mem_loc_1 = self.memory.read(opcode_pt_1)? as u16;
+        let mem_loc_2 = self.memory.read(opcode_pt_2)? as u16;
+        self.opcode = mem_loc_1 << 8 | mem_loc_2;
 
         let decode = self.opcode & 0xF000;
 
+        if let Some(category) = profile::category(self.opcode) {
+            self.profile[category] += 1;
+        }
+        self.hotspots[opcode_pt_1] += 1;
+
+        let in_idle_loop = match self.idle_loop {
+            Some((start, end)) => self.pc >= start && self.pc <= end,
+            None => false,
+        };
+        if in_idle_loop || self.waiting_for_key {
+            self.idle_cycles += 1;
+        } else {
+            self.idle_loop = None;
+        }
+
         CPU::debug_opcode(self.opcode, decode);
-        println!("Pt1: {}, Pt2: {}", opcode_pt_1, opcode_pt_2);
-        println!(
+        trace!("Pt1: {}, Pt2: {}", opcode_pt_1, opcode_pt_2);
+        trace!(
             "Memory Loc 1: {:#06x}, Memory Loc 2: {:#06x}",
-            self.memory[opcode_pt_1], self.memory[opcode_pt_2]
+            mem_loc_1,
+            mem_loc_2
         );
 
+        for name in quirks::affecting(self.opcode) {
+            if self.ambiguous_quirks.contains(name) {
+                return Err(EmulateError::AmbiguousOpcode(self.pc, self.opcode));
+            }
+        }
+
         match decode {
             0x0000 => match self.opcode & 0x00FF {
                 // 00E0: Clears the screen
@@ -120,30 +587,69 @@ impl CPU {
                     for i in self.gfx.iter_mut() {
                         *i = 0;
                     }
+                    self.mark_all_dirty();
 
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // 00CN: Scroll display N lines down
+                n if n & 0x00F0 == 0x00C0 => {
+                    self.scroll(0, (n & 0x000F) as i32);
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // 00FB: Scroll display 4 pixels right
+                0x00FB => {
+                    self.scroll(4, 0);
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // 00FC: Scroll display 4 pixels left
+                0x00FC => {
+                    self.scroll(-4, 0);
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // 00FE: Disable high resolution mode
+                0x00FE => {
+                    self.set_hires(false);
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                // 00FF: Enable high resolution mode
+                0x00FF => {
+                    self.set_hires(true);
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 00EE Returns from a subroutine
                 0x0EE => {
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp as usize] as u16;
-                    self.pc += 2;
+                    self.sp = self.sp.checked_sub(1).ok_or(EmulateError::StackUnderflow)?;
+                    self.pc = self.stack[self.sp as usize].wrapping_add(2);
                 }
                 // 0NNN: Jump to machine code routine - Interpreter will ignore
                 _ => {
                     // TODO Jump to machine code routine
                     CPU::debug_opcode(self.opcode, decode);
-                    process::exit(0x0100);
+                    self.unknown_opcode()?;
                 }
             },
             // 1NNN: Jumps to address NNN.
             0x1000 => {
-                let jump_loc = self.opcode & 0x0FFF;
+                let jump_loc = self.mask_address(self.opcode & 0x0FFF);
+                if jump_loc == self.pc {
+                    self.halted = true;
+                }
+                // Later passes through a loop that only polls the timers or
+                // keys are counted as idle
+                if jump_loc <= self.pc
+                    && profile::is_busy_wait(&*self.memory, jump_loc as usize, self.pc as usize)
+                {
+                    self.idle_loop = Some((jump_loc, self.pc));
+                }
                 self.pc = jump_loc;
             }
             // 2NNN: Calls subroutine at NNN
             0x2000 => {
-                let jump_loc = self.opcode & 0x0FFF;
+                let jump_loc = self.mask_address(self.opcode & 0x0FFF);
+
+                if self.sp as usize >= self.stack.len() {
+                    return Err(EmulateError::StackOverflow);
+                }
 
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
@@ -152,45 +658,36 @@ impl CPU {
             // 3XNN: Skip next instruction if VX equals NN
             0x3000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                if self.V[VX] == (self.opcode & 0x00FF) as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                let NN = (self.opcode & 0x00FF) as u8;
+                self.skip_if(self.V[VX] == NN)?;
             }
             // 4XNN: Skip next instruction if VX does not equal NN
             0x4000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                if self.V[VX] != (self.opcode & 0x00FF) as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                let NN = (self.opcode & 0x00FF) as u8;
+                self.skip_if(self.V[VX] != NN)?;
             }
             // 5XY0: Skips the next instruction if VX equals VY. (Usually the
             // next instruction is a jump to skip a code block)
             0x5000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                 let VY = ((self.opcode & 0x00F0) >> 4) as usize;
-                if self.V[VX] == self.V[VY] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.V[VX] == self.V[VY])?;
             }
             // 6XNN: Sets VX to NN
             0x6000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                 let NN = self.opcode & 0x00FF;
                 self.V[VX] = NN as u8;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
             // 7XNN: Adds NN to VX
             0x7000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                let NN = (self.opcode & 0x00FF);
-                self.V[VX] = (self.V[VX] as u16 + NN) as u8;
-                self.pc += 2;
+                let NN = (self.opcode & 0x00FF) as u8;
+                // Carry is discarded, VF is not affected
+                self.V[VX] = self.V[VX].wrapping_add(NN);
+                self.pc = self.pc.wrapping_add(2);
             }
             0x8000 => match self.opcode & 0x000F {
                 // Sets VX to value of VY
@@ -199,28 +696,37 @@ impl CPU {
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
                     let VY = self.V[VY];
                     self.V[VX] = VY;
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY1: Sets VX to VX or VY. (Bitwise OR operation)
                 0x0001 => {
                     let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
-                    self.V[VX as usize] = (self.V[VX] | self.V[VY]) as u8;
-                    self.pc += 2;
+                    self.V[VX] |= self.V[VY];
+                    if self.quirks.logic_resets_vf {
+                        self.V[0xF] = 0;
+                    }
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY2: Sets VX to VX and VY. (Bitwise AND operation)
                 0x0002 => {
                     let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
-                    self.V[VX] = (self.V[VX] & self.V[VY]) as u8;
-                    self.pc += 2;
+                    self.V[VX] &= self.V[VY];
+                    if self.quirks.logic_resets_vf {
+                        self.V[0xF] = 0;
+                    }
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY3: Sets VX to VX xor VY
                 0x0003 => {
                     let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
-                    self.V[VX as usize] = (self.V[VX] ^ self.V[VY]) as u8;
-                    self.pc += 2;
+                    self.V[VX] ^= self.V[VY];
+                    if self.quirks.logic_resets_vf {
+                        self.V[0xF] = 0;
+                    }
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY4: Adds VY to VX. VF is set to 1 when there's a carry,
                 // and to 0 when there isn't.
@@ -228,13 +734,13 @@ impl CPU {
                     let VX = (self.opcode & 0x0F00) >> 8;
                     let VY = (self.opcode & 0x00F0) >> 4;
 
-                    let add = self.V[VX as usize] as u16 + self.V[VY as usize] as u16;
+                    let (add, carry) = self.V[VX as usize].overflowing_add(self.V[VY as usize]);
 
-                    self.V[0xf] = if add > 255 { 1 } else { 0 };
+                    self.V[0xf] = carry as u8;
 
-                    self.V[VX as usize] = add as u8;
+                    self.V[VX as usize] = add;
 
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY5: VY is subtracted from VX. VF is set to 0 when there's
                 // a borrow, and 1 when there isn't.
@@ -242,46 +748,54 @@ impl CPU {
                     let VX = (self.opcode & 0x0F00) >> 8;
                     let VY = (self.opcode & 0x00F0) >> 4;
 
-                    let sub = ((self.V[VX as usize] as i16) - (self.V[VY as usize] as i16));
+                    let (sub, borrow) = self.V[VX as usize].overflowing_sub(self.V[VY as usize]);
 
-                    self.V[0xf] = if sub < 0 { 0 } else { 1 };
+                    self.V[0xf] = !borrow as u8;
 
-                    self.V[VX as usize] = sub as u8;
+                    self.V[VX as usize] = sub;
 
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
-                // 8XY6: Stores the least significant bit of VX in VF and then
-                // shifts VX to the right by 1.
+                // 8XY6: Sets VX to VY shifted to the right by 1 and stores the
+                // bit shifted out in VF. VF is written last so 8FY6 keeps the
+                // flag.
                 0x0006 => {
                     let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
 
-                    self.V[0xF] = self.V[VX] & 0b0001;
+                    let VY = if self.quirks.shift_uses_vx { VX } else { VY };
+
+                    let flag = self.V[VY] & 0b0001;
                     self.V[VX] = self.V[VY] >> 1;
+                    self.V[0xF] = flag;
 
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 // 8XY7: Sets VX to VY minus VX. VF is set to 0 when there's a
                 // borrow, and 1 when there isn't.
                 0x0007 => {
                     CPU::debug_opcode(self.opcode, decode);
-                    process::exit(0x0100);
+                    self.unknown_opcode()?;
                 }
-                // 8XYE: Stores the most significant bit of VX in VF and then
-                // shifts VX to the left by 1.
+                // 8XYE: Sets VX to VY shifted to the left by 1 and stores the
+                // bit shifted out in VF. VF is written last so 8FYE keeps the
+                // flag.
                 0x000E => {
                     let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                     let VY = ((self.opcode & 0x00F0) >> 4) as usize;
 
-                    self.V[0xF] = self.V[VX] >> 3;
+                    let VY = if self.quirks.shift_uses_vx { VX } else { VY };
+
+                    let flag = self.V[VY] >> 7;
                     self.V[VX] = self.V[VY] << 1;
+                    self.V[0xF] = flag;
 
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
                 _ => {
-                    println!("0x8XYN Undetermined Opcode!");
+                    warn!("0x8XYN Undetermined Opcode!");
                     CPU::debug_opcode(self.opcode, decode);
-                    process::exit(0x0100);
+                    self.unknown_opcode()?;
                 }
             },
             // 9XY0: Skips the next instruction if VX doesn't equal VY.
@@ -289,60 +803,134 @@ impl CPU {
             0x9000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                 let VY = ((self.opcode & 0x00F0) >> 4) as usize;
-
-                if self.V[VX] != self.V[VY] {
-                    self.pc += 4
-                } else {
-                    self.pc += 2
-                }
+                self.skip_if(self.V[VX] != self.V[VY])?;
             }
             // ANNN: Set I to address at NNN
             0xA000 => {
-                self.I = (self.opcode & 0x0FFF);
-                self.pc += 2;
+                self.set_i(self.opcode & 0x0FFF);
+                self.pc = self.pc.wrapping_add(2);
+            }
+            // BNNN: Jumps to the address NNN plus V0
+            // With the jump_uses_vx quirk this is BXNN, adding VX instead
+            0xB000 => {
+                let VX = if self.quirks.jump_uses_vx {
+                    ((self.opcode & 0x0F00) >> 8) as usize
+                } else {
+                    0
+                };
+                let jump_loc = (self.opcode & 0x0FFF).wrapping_add(self.V[VX] as u16);
+                self.pc = self.mask_address(jump_loc);
             }
             // CXNN: Sets VX to the result of a bitwise and operation on a
             // random number (Typically: 0 to 255) and NN.
             0xC000 => {
-                let mut rng = thread_rng();
-                let num: u16 = rng.gen_range(0, 255);
+                let num: u8 = self.rng.gen();
 
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                let NN = (self.opcode & 0x00FF);
+                let NN = (self.opcode & 0x00FF) as u8;
 
-                self.V[VX] = (num & NN) as u8;
+                self.V[VX] = num & NN;
 
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
             // DXYN: Draw at (Vx, Vy, N)
+            // In high resolution mode DXY0 draws a 16x16 sprite stored as two
+            // bytes per row. In low resolution mode it draws nothing, or an
+            // 8x16 sprite with the lores_dxy0_tall quirk.
+            //
+            // Wrapping happens at two levels. The starting position always
+            // wraps, so a sprite drawn at X=70 appears at X=6. The individual
+            // pixels of a sprite that then runs off the edge are either
+            // clipped or wrapped around to the other side depending on the
+            // clip_sprites quirk.
             0xD000 => {
                 let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                 let VY = ((self.opcode & 0x00F0) >> 4) as usize;
                 let n: u16 = (self.opcode & 0x000F) as u16; // Height of gfx
 
-                let x: u16 = self.V[VX] as u16;
-                let y: u16 = self.V[VY] as u16;
-                let w = (self.width - 1) as u16;
+                let x = self.V[VX] as u32 % self.width;
+                let y = self.V[VY] as u32 % self.height;
+                let clip = self.quirks.clip_sprites;
+
+                let (rows, cols) = if n == 0 && self.hires {
+                    (16, 16)
+                } else if n == 0 && self.quirks.lores_dxy0_tall {
+                    (16, 8)
+                } else {
+                    (n, 8)
+                };
+
+                // Pixels the sprite turned off, for the collision log. Only
+                // collected when it will be written.
+                let log_collisions = log_enabled!(Level::Debug);
+                let mut collisions = Vec::new();
 
                 self.V[0xF] = 0;
-                for i in 0..n {
-                    let pixel = self.memory[(self.I + i) as usize];
-                    println!("{:#08b}", pixel);
-                    for j in 0..8 {
-                        if pixel & (0x80 >> j) != 0 {
-                            let loc = x + j + ((y + i) * 64);
-                            if self.gfx[loc as usize] == 1 {
-                                self.V[0xF] = 1;
+                for i in 0..rows {
+                    // Addresses are widened first so a sprite near the top
+                    // of the address space reads past the end of memory
+                    // rather than overflowing I
+                    let I = self.I as usize;
+                    let i = i as usize;
+                    let pixel = if cols == 16 {
+                        let hi = self.sprite_byte(I + i * 2)? as u16;
+                        let lo = self.sprite_byte(I + i * 2 + 1)? as u16;
+                        hi << 8 | lo
+                    } else {
+                        self.sprite_byte(I + i)? as u16
+                    };
+                    trace!("{:#08b}", pixel);
+
+                    let mut py = y + i as u32;
+                    if py >= self.height {
+                        if clip {
+                            break;
+                        }
+                        py %= self.height;
+                    }
+
+                    for j in 0..cols {
+                        // Only AND changes pixels under a blank sprite bit
+                        let bit = pixel & (1 << (cols - 1 - j)) != 0;
+                        if !bit && self.draw_mode != DrawMode::And {
+                            continue;
+                        }
+
+                        let mut px = x + j as u32;
+                        if px >= self.width {
+                            if clip {
+                                break;
                             }
-                            self.gfx[loc as usize] ^= 1;
+                            px %= self.width;
+                        }
+
+                        let loc = (px + py * self.width) as usize;
+                        let old = self.gfx[loc];
+                        if self.draw_mode == DrawMode::Xor && old == 1 {
+                            self.V[0xF] = 1;
+                            if log_collisions {
+                                collisions.push(format!("({}, {})", px, py));
+                            }
+                        }
+                        self.gfx[loc] = self.draw_mode.apply(old, bit);
+                        if self.gfx[loc] != old {
+                            self.mark_dirty(px, py);
                         }
                     }
                 }
+
+                if !collisions.is_empty() {
+                    debug!(
+                        "Collision at cycle {}: {}",
+                        self.cycles,
+                        collisions.join(", ")
+                    );
+                }
                 //let mut line = String::new();
                 //let _ = std::io::stdin().read_line(&mut line).unwrap();
                 //process::exit(0x0100);
 
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
             0xE000 => {
                 match self.opcode & 0x00FF {
@@ -351,79 +939,103 @@ impl CPU {
                     // skip a code block)
                     0x009e => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                        if self.V[VX] == self.k {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                        self.k = 0xff; // Reset key press
+                        self.skip_if(self.keys[(self.V[VX] & 0x0F) as usize])?;
                     }
                     // EXA1: Skips the next instruction if the key stored in VX
                     // isn't pressed. (Usually the next instruction is a jump
                     // to skip a code block)
                     0x00a1 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                        if self.V[VX] != self.k {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                        self.k = 0xff; // Reset key press
+                        self.skip_if(!self.keys[(self.V[VX] & 0x0F) as usize])?;
                     }
                     _ => {
-                        println!("Undetermined Opcode!");
+                        warn!("Undetermined Opcode!");
                         CPU::debug_opcode(self.opcode, decode);
-                        process::exit(0x0100);
+                        self.unknown_opcode()?;
                     }
                 }
             }
             // FNNN: Opcodes for F parsed here
             0xF000 => {
                 match self.opcode & 0x00FF {
+                    // F000 NNNN: Sets I to the 16 bit address in the next
+                    // word (XO-CHIP). The whole four bytes are stepped over.
+                    0x0000 if self.opcode == 0xF000 => {
+                        let addr = self.pc as usize + 2;
+                        if addr + 1 >= self.memory.size() {
+                            return Err(EmulateError::OutOfBounds(addr + 1));
+                        }
+                        let nnnn = self.read_word(addr)?;
+                        self.set_i(nnnn);
+                        self.pc = self.pc.wrapping_add(4);
+                    }
                     // FX0A: A key press is awaited, and then stored in VX.
                     // (Blocking Operation. All instruction halted until next
                     // key event)
                     0x000A => {
-                        println!("Waiting for key press");
-                        println!("{} != 255", self.k);
-                        if self.k != 0xff {
-                            self.pc += 2;
-                            let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                            self.V[VX] = self.k;
+                        trace!("Waiting for key press");
+                        let VX = ((self.opcode & 0x0F00) >> 8) as usize;
+                        let pressed = (0..16)
+                            .find(|k| self.keys[*k] && !self.fx0a_consumed[*k])
+                            .map(|k| k as u8);
+
+                        let pc = self.pc;
+                        if self.quirks.fx0a_on_release {
+                            // Remember the first key pressed and only complete
+                            // once that same key has been let go
+                            match self.fx0a_key {
+                                Some(key) if !self.keys[key as usize] => {
+                                    self.V[VX] = key;
+                                    self.fx0a_key = None;
+                                    self.fx0a_consumed = [false; 16];
+                                    self.pc = self.pc.wrapping_add(2);
+                                }
+                                Some(_) => {}
+                                None => self.fx0a_key = pressed,
+                            }
+                        } else if let Some(key) = pressed {
+                            self.V[VX] = key;
+                            self.fx0a_consumed[key as usize] = true;
+                            self.pc = self.pc.wrapping_add(2);
                         }
-                        self.k = 0xff; // Reset key press
+                        self.waiting_for_key = self.pc == pc;
                     }
                     //FX1e: Adds VX to I. VF is not affected
                     0x001e => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                         let inc = self.V[VX];
-                        self.I += inc as u16;
-                        self.pc += 2;
+                        let sum = self.I.wrapping_add(inc as u16);
+                        if self.quirks.fx1e_sets_vf {
+                            self.V[0xF] = (sum > 0x0FFF) as u8;
+                        }
+                        self.set_i(sum);
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX07: Store the current value of the delay timer in
                     // register VX
                     0x0007 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                         self.V[VX] = self.delay_timer;
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX15: Set the delay timer to the value of register VX
                     0x0015 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                         self.delay_timer = self.V[VX];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX18: Sets the sound timer to VX
                     0x0018 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
                         self.sound_timer = self.V[VX];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX29: Sets I to the location of sprite in VX
+                    // Each glyph is 5 bytes, only the low nibble selects one
                     0x0029 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                        self.I = 80 + (self.V[VX] * 5) as u16;
-                        self.pc += 2;
+                        self.I = FONTSET_START as u16 + (self.V[VX] as u16 & 0x0F) * 5;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX33: Store binary-coded decimal values in memory
                     // Hundreds digit in memory location I
@@ -431,11 +1043,17 @@ impl CPU {
                     // Ones digit in memory location I+2
                     0x0033 => {
                         let VX = ((self.opcode & 0x0F00) >> 8) as usize;
-                        self.memory[self.I as usize] = (self.V[VX] / 100) as u16;
-                        self.memory[(self.I + 1) as usize] = ((self.V[VX] / 10) % 10) as u16;
-                        self.memory[(self.I + 2) as usize] = ((self.V[VX] % 100) % 10) as u16;
+                        let I = self.I as usize;
+
+                        // Nothing is written unless all three digits fit
+                        if I + 2 >= self.memory.size() {
+                            return Err(EmulateError::OutOfBounds(I + 2));
+                        }
+                        self.write_mem(I, self.V[VX] / 100)?;
+                        self.write_mem(I + 1, (self.V[VX] / 10) % 10)?;
+                        self.write_mem(I + 2, (self.V[VX] % 100) % 10)?;
 
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX55: Stores V0 to VX (including VX) in memory starting at
                     // address I. The offset from I is increased by 1 for each value
@@ -444,11 +1062,14 @@ impl CPU {
                         let VX = (self.opcode & 0x0F00) >> 8;
                         for x in 0..VX + 1 {
                             let V_index = x as usize;
-                            let memory_index = (self.I + x) as usize;
-                            self.memory[memory_index] = self.V[V_index] as u16;
+                            let memory_index = self.I as usize + V_index;
+                            self.write_mem(memory_index, self.V[V_index])?;
+                        }
+                        if self.quirks.load_store_increments_i {
+                            self.set_i(self.I.wrapping_add(VX + 1));
                         }
 
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     // FX65: Fill V0 to VX with values starting from memory I
                     // I is increased by 1 each cycle, but is left unmodified
@@ -456,122 +1077,2410 @@ impl CPU {
                         let VX = (self.opcode & 0x0F00) >> 8;
                         for x in 0..VX + 1 {
                             let V_index = x as usize;
-                            let memory_index = (self.I + x) as usize;
-                            self.V[V_index] = self.memory[memory_index] as u8;
+                            let memory_index = self.I as usize + V_index;
+                            self.V[V_index] = self.memory.read(memory_index)?;
+                        }
+                        if self.quirks.load_store_increments_i {
+                            self.set_i(self.I.wrapping_add(VX + 1));
                         }
 
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     _ => {
-                        println!("2) Undetermined Opcode!");
+                        warn!("2) Undetermined Opcode!");
                         CPU::debug_opcode(self.opcode, decode);
-                        process::exit(0x0100);
+                        self.unknown_opcode()?;
                     }
                 }
             }
             // Exit and print last opcode
             _ => {
-                println!("1) Undetermined Opcode!");
+                warn!("1) Undetermined Opcode!");
                 CPU::debug_opcode(self.opcode, decode);
-                process::exit(0x0100);
+                self.unknown_opcode()?;
             }
         }
-    }
 
-    fn debug_opcode(opcode: u16, decode: u16) {
-        println!("\nOpcode: {:#06x}", opcode);
-        println!("Decode: {:#06x}", decode)
+        self.cycles += 1;
+
+        if self.trace.is_some() {
+            let line = trace::format_state(self, self.trace_format);
+            if let Some(trace) = self.trace.as_mut() {
+                writeln!(trace, "{}", line)?;
+            }
+        }
+
+        Ok(())
     }
 
-    fn debug_cpu_registers(V: [u16; 16]) {
-        for (i, code) in V.iter().enumerate() {
-            println!("V[{}]: {:#06x}", i, code);
+    // Switches between 64x32 and 128x64 display modes. The display is
+    // cleared unless the quirk asks for it to be carried across.
+    fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires { (128, 64) } else { (64, 32) };
+        let mut gfx = vec![0; (width * height) as usize];
+
+        if self.quirks.resolution_keeps_display {
+            for y in 0..height {
+                for x in 0..width {
+                    let src_x = x * self.width / width;
+                    let src_y = y * self.height / height;
+                    gfx[(x + y * width) as usize] = self.gfx[(src_x + src_y * self.width) as usize];
+                }
+            }
         }
+
+        self.hires = hires;
+        self.width = width;
+        self.height = height;
+        self.gfx = gfx;
+        self.mark_all_dirty();
     }
-}
 
-fn main() {
-    let height: usize = 32;
-    let width: usize = 64;
+    // Monochrome display packed 8 pixels to a byte, most significant bit
+    // first
+    fn pack_display(&self) -> Vec<u8> {
+        self.gfx
+            .chunks(8)
+            .map(|pixels| {
+                pixels.iter().enumerate().fold(
+                    0,
+                    |byte, (i, pixel)| if *pixel != 0 { byte | 0x80 >> i } else { byte },
+                )
+            })
+            .collect()
+    }
 
-    let gfx: Vec<u32> = vec![0; width * height];
+    // Grows the dirty box to take in a changed pixel
+    fn mark_dirty(&mut self, x: u32, y: u32) {
+        self.dirty = Some(match self.dirty {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+            None => (x, y, x, y),
+        });
+    }
 
-    //let path = "pong.ch8";
-    let path = "c8games/UFO";
-    let mut cpu = CPU::initialize(path, gfx);
+    fn mark_all_dirty(&mut self) {
+        self.dirty = Some((0, 0, self.width - 1, self.height - 1));
+    }
 
-    let mut window = Window::new(
-        "Chip-8 - Press ESC to exit",
-        width,
-        height,
-        WindowOptions {
-            //resize: true,
-            scale: Scale::X16,
-            ..WindowOptions::default()
-        },
-    )
-    .expect("Unable to create window");
+    // Pixels changed since the last call, if any
+    fn take_dirty(&mut self) -> Option<(u32, u32, u32, u32)> {
+        self.dirty.take()
+    }
+
+    // Restores a display packed by pack_display at the current resolution
+    fn unpack_display(&mut self, data: &[u8]) -> Result<(), EmulateError> {
+        if data.len() != self.gfx.len().div_ceil(8) {
+            return Err(EmulateError::InvalidDisplayData(data.len()));
+        }
 
-    // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+        for (i, pixel) in self.gfx.iter_mut().enumerate() {
+            *pixel = ((data[i / 8] >> (7 - i % 8)) & 1) as u32;
+        }
+        self.mark_all_dirty();
 
-    // Pixel colors
-    let pixel_color_white = std::u32::MAX;
-    let pixel_color_black = 0;
+        Ok(())
+    }
 
-    // Abstraction for displaying graphics
-    let mut buffer: Vec<u32> = vec![0; width * height];
-
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        window.get_keys().map(|keys| {
-            for t in keys {
-                match t {
-                    Key::Key1 => cpu.k = 0x1,
-                    Key::Key2 => cpu.k = 0x2,
-                    Key::Key3 => cpu.k = 0x3,
-                    Key::Key4 => cpu.k = 0xc,
-                    Key::Q => cpu.k = 0x4,
-                    Key::W => cpu.k = 0x5,
-                    Key::E => cpu.k = 0x6,
-                    Key::R => cpu.k = 0xd,
-                    Key::A => cpu.k = 0x7,
-                    Key::S => cpu.k = 0x8,
-                    Key::D => cpu.k = 0x9,
-                    Key::F => cpu.k = 0xe,
-                    Key::Z => cpu.k = 0xa,
-                    Key::X => cpu.k = 0x0,
-                    Key::C => cpu.k = 0xb,
-                    Key::V => cpu.k = 0xf,
-                    _ => cpu.k = 0xff,
+    // Shifts the display by (dx, dy) pixels, filling with blank pixels
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let (dx, dy) = if self.quirks.half_pixel_scroll && !self.hires {
+            (dx / 2, dy / 2)
+        } else {
+            (dx, dy)
+        };
+        let width = self.width as i32;
+        let height = self.height as i32;
+        let mut gfx = vec![0; self.gfx.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    gfx[(x + y * width) as usize] = self.gfx[(src_x + src_y * width) as usize];
                 }
             }
-        });
+        }
 
-        println!("Current Key Register: {}", cpu.k);
-        cpu.emulate_cycle();
+        self.gfx = gfx;
+        self.mark_all_dirty();
+    }
 
-        for (index, i) in cpu.gfx.iter_mut().enumerate() {
-            let mut color = pixel_color_black;
-            if *i == 1 {
-                color = pixel_color_white;
-            }
-            buffer[index] = color;
+    // Updates every held key at once, as polled from the window or a
+    // replay script
+    fn set_keys(&mut self, keys: [bool; 16]) -> Result<(), EmulateError> {
+        for (key, down) in keys.iter().enumerate() {
+            self.set_key(key, *down)?;
         }
 
-        // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
-        window.update_with_buffer(&buffer, width, height).unwrap();
+        Ok(())
+    }
+
+    // The buzzer sounds for as long as the sound timer is nonzero
+    fn beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // Whether the ROM is blocked on FX0A until a key is pressed
+    fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    // Which of the hex keys are held
+    fn key_state(&self) -> [bool; 16] {
+        self.keys
+    }
+
+    // Presses or releases a single hex key, a released key can complete
+    // FX0A again
+    fn set_key(&mut self, key: usize, down: bool) -> Result<(), EmulateError> {
+        let held = self
+            .keys
+            .get_mut(key)
+            .ok_or(EmulateError::InvalidKey(key))?;
+        *held = down;
+        self.fx0a_consumed[key] &= down;
+
+        Ok(())
+    }
+
+    // Counts the timers down once for every 60th of a second in dt, carrying
+    // any remainder over to the next call. This is the only place the timers
+    // decrement, opcodes just read and write them.
+    fn tick_timers(&mut self, dt: Duration) {
+        self.timer_elapsed += dt;
 
-        if cpu.delay_timer > 0 {
-            cpu.delay_timer -= 1;
+        while self.timer_elapsed >= FRAME {
+            self.timer_elapsed -= FRAME;
+            self.decrement_timers();
+        }
+    }
+
+    fn decrement_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
         }
 
-        if cpu.sound_timer > 0 {
-            cpu.sound_timer -= 1;
-            if cpu.sound_timer == 1 {
-                println!("BEEP!");
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+            if self.sound_timer == 1 {
+                info!("BEEP!");
             }
         }
-        cpu.k = 0xff; // Reset key press
     }
-    process::exit(0x0100);
+
+    fn debug_opcode(opcode: u16, decode: u16) {
+        trace!("Opcode: {:#06x}", opcode);
+        trace!("Decode: {:#06x}", decode)
+    }
+
+    fn debug_cpu_registers(V: [u16; 16]) {
+        for (i, code) in V.iter().enumerate() {
+            debug!("V[{}]: {:#06x}", i, code);
+        }
+    }
+}
+
+// Runs one frame: a batch of instructions then advances the timers by the
+// time the frame took
+fn run_frame(cpu: &mut CPU, budget: FrameBudget, dt: Duration) -> Result<(), EmulateError> {
+    run_frame_until(cpu, budget, dt, |_| false)
+}
+
+// Same as run_frame, but returns before the next instruction (and without
+// ticking the timers) as soon as stop holds
+fn run_frame_until<F: Fn(&CPU) -> bool>(
+    cpu: &mut CPU,
+    budget: FrameBudget,
+    dt: Duration,
+    stop: F,
+) -> Result<(), EmulateError> {
+    let mut used = 0;
+
+    while used < budget.limit {
+        if stop(cpu) {
+            return Ok(());
+        }
+
+        cpu.emulate_cycle()?;
+        used += budget.cost(cpu.opcode);
+
+        // The rest of the frame is spent waiting for the display
+        if cpu.quirks.display_wait && cpu.opcode & 0xF000 == 0xD000 {
+            break;
+        }
+    }
+    cpu.tick_timers(dt);
+
+    Ok(())
+}
+
+// Runs without a window until the ROM halts, giving up after max_cycles
+// instructions so a ROM that never halts can't run forever
+fn run_headless(
+    cpu: &mut CPU,
+    input: &mut dyn Input,
+    max_cycles: u64,
+    budget: FrameBudget,
+    mut stop: impl FnMut(&CPU, u64) -> bool,
+) -> Result<(), EmulateError> {
+    let mut frame = 0;
+
+    // Each frame takes exactly 1/60th of a second of simulated time
+    let mut clock = MockClock::default();
+    let mut last_frame = clock.now();
+
+    while !cpu.halted {
+        if cpu.cycles >= max_cycles {
+            return Err(EmulateError::CycleLimitExceeded(max_cycles));
+        }
+
+        cpu.set_keys(input.poll(frame))?;
+
+        clock.advance(FRAME);
+        run_frame_until(cpu, budget, clock.elapsed(last_frame), |cpu| {
+            cpu.halted || cpu.cycles >= max_cycles
+        })?;
+        last_frame = clock.now();
+
+        // Checked after every frame, once the frame has finished drawing
+        if stop(cpu, frame) {
+            break;
+        }
+        frame += 1;
+    }
+
+    Ok(())
+}
+
+// Runs without a window until the display has stopped changing for
+// `frames` frames in a row
+fn run_until_stable(
+    cpu: &mut CPU,
+    frames: u32,
+    max_cycles: u64,
+    budget: FrameBudget,
+) -> Result<(), EmulateError> {
+    let mut unchanged = 0;
+
+    while unchanged < frames && !cpu.halted {
+        if cpu.cycles >= max_cycles {
+            return Err(EmulateError::CycleLimitExceeded(max_cycles));
+        }
+
+        let before = cpu.gfx.clone();
+        run_frame_until(cpu, budget, FRAME, |cpu| cpu.cycles >= max_cycles)?;
+
+        if cpu.gfx == before {
+            unchanged += 1;
+        } else {
+            unchanged = 0;
+        }
+    }
+
+    Ok(())
+}
+
+// Runs at full speed until `target` instructions have executed, stopping
+// early if the ROM halts
+fn run_to(cpu: &mut CPU, target: u64, budget: FrameBudget) -> Result<(), EmulateError> {
+    while cpu.cycles < target && !cpu.halted {
+        run_frame_until(cpu, budget, FRAME, |cpu| cpu.halted || cpu.cycles >= target)?;
+    }
+
+    Ok(())
+}
+
+// Debugging aids drawn over the display, each toggled with a function key
+#[derive(Default)]
+struct Overlays {
+    // The keypad, toggled with F2
+    keypad: bool,
+
+    // Memory as a grayscale image, toggled with F6
+    memory: bool,
+
+    // The next instruction, toggled with F7
+    opcode: bool,
+}
+
+impl Overlays {
+    fn toggle(&mut self, window: &Window) {
+        if window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            self.keypad = !self.keypad;
+        }
+        if window.is_key_pressed(Key::F6, KeyRepeat::No) {
+            self.memory = !self.memory;
+        }
+        if window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            self.opcode = !self.opcode;
+        }
+    }
+}
+
+// Pauses while the window is in the background. Regaining focus only
+// resumes if it was losing focus that paused, not the P key.
+#[derive(Default)]
+struct BlurPause {
+    paused_by_blur: bool,
+}
+
+impl BlurPause {
+    fn update(&mut self, focused: bool, paused: &mut bool) {
+        if !focused && !*paused {
+            *paused = true;
+            self.paused_by_blur = true;
+        } else if focused && self.paused_by_blur {
+            *paused = false;
+            self.paused_by_blur = false;
+        }
+    }
+}
+
+// Keys held this frame, from the replay script when there is one
+fn poll_keys(
+    window: &Window,
+    keymap: &Keymap,
+    replay: &mut Option<ScriptedInput>,
+    frame: u64,
+) -> [bool; 16] {
+    match replay {
+        Some(replay) => replay.poll(frame),
+        None => WindowInput { window, keymap }.poll(frame),
+    }
+}
+
+// Carries out a step mode command, returning false once the user quits
+fn run_command(
+    cpu: &mut CPU,
+    command: Command,
+    budget: FrameBudget,
+    debugger: &mut Debugger,
+) -> Result<bool, EmulateError> {
+    match command {
+        Command::Step => cpu.emulate_cycle()?,
+        Command::Frame => run_frame(cpu, budget, FRAME)?,
+        Command::Set(addr, val) => {
+            if let Err(e) = cpu.set_mem(addr, val) {
+                println!("Unable to write memory: {}", e);
+            }
+        }
+        Command::Mem(addr, len) => match cpu.dump_memory(addr, len) {
+            Ok(dump) => print!("{}", dump),
+            Err(e) => println!("Unable to read memory: {}", e),
+        },
+        Command::ClearRegs(first, last) => {
+            for reg in first..=last {
+                cpu.set_reg(reg, 0)?;
+            }
+            println!("Cleared V{:X}-V{:X}", first, last);
+        }
+        Command::Quirks => {
+            for name in quirks::NAMES.iter() {
+                let on = cpu.quirks.get(name).unwrap_or_default();
+                println!("{} {}", name, if on { "on" } else { "off" });
+            }
+        }
+        Command::Quirk(name, on) => match cpu.quirks.set(&name, on) {
+            // Chosen now, so strict mode no longer refuses it
+            Ok(()) => cpu.ambiguous_quirks.retain(|quirk| *quirk != name),
+            Err(e) => println!("{}", e),
+        },
+        Command::Break(addr) => {
+            debugger.breakpoints.push(addr);
+            println!("Breakpoint at {:03X}", addr);
+        }
+        // The first instruction runs here so a breakpoint at the current
+        // address doesn't stop the run straight away
+        Command::Continue => {
+            cpu.emulate_cycle()?;
+            debugger.running = true;
+        }
+        Command::Quit => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// Runs one frame of a continue, returning whether it stopped at a
+// breakpoint, a halt or an error
+fn continue_frame(
+    cpu: &mut CPU,
+    debugger: &mut Debugger,
+    budget: FrameBudget,
+    dt: Duration,
+) -> bool {
+    let breakpoints = &debugger.breakpoints;
+    let result = run_frame_until(cpu, budget, dt, |cpu| {
+        cpu.halted || breakpoints.contains(&cpu.pc)
+    });
+
+    let stopped = match result {
+        Ok(()) => cpu.halted || debugger.breakpoints.contains(&cpu.pc),
+        Err(e) => {
+            println!("{}", e);
+            true
+        }
+    };
+    if stopped {
+        debugger.running = false;
+    }
+
+    stopped
+}
+
+// Output buffer handed to the window. The display is colored into a
+// second buffer, recoloring only the pixels that changed, which is then
+// copied under the overlays each frame.
+struct Screen {
+    buffer: Vec<u32>,
+    colored: Vec<u32>,
+
+    // Per-pixel brightness used when fading is enabled
+    intensity: Vec<u8>,
+
+    width: usize,
+    height: usize,
+
+    // Whether colored was drawn with the on and off colors swapped
+    invert: bool,
+}
+
+impl Screen {
+    fn new(width: usize, height: usize) -> Screen {
+        Screen {
+            buffer: vec![0; width * height],
+            colored: vec![0; width * height],
+            intensity: vec![0; width * height],
+            width,
+            height,
+            invert: false,
+        }
+    }
+
+    // Colors the display into the output buffer, then draws the overlays
+    // on top
+    fn draw(&mut self, cpu: &mut CPU, options: &Options, invert: bool, overlays: &Overlays) {
+        // Follow resolution changes, minifb stretches the buffer to the
+        // window
+        if cpu.gfx.len() != self.buffer.len() {
+            *self = Screen::new(cpu.width as usize, cpu.height as usize);
+            cpu.mark_all_dirty();
+        }
+        if invert != self.invert {
+            self.invert = invert;
+            cpu.mark_all_dirty();
+        }
+        let dirty = cpu.take_dirty();
+
+        if options.fade > 0 {
+            // Every lit pixel fades a little each frame, changed or not
+            let off = display::color(0, &options.palette, invert);
+            let on = display::color(1, &options.palette, invert);
+
+            display::fade(&mut self.intensity, &cpu.gfx, options.fade);
+            for (index, level) in self.intensity.iter().enumerate() {
+                self.colored[index] = display::shade(*level, off, on);
+            }
+        } else if let Some((x0, y0, x1, y1)) = dirty {
+            for y in y0 as usize..=y1 as usize {
+                for x in x0 as usize..=x1 as usize {
+                    let index = x + y * self.width;
+                    self.colored[index] = display::color(cpu.gfx[index], &options.palette, invert);
+                }
+            }
+        }
+        self.buffer.copy_from_slice(&self.colored);
+
+        let (buffer, width) = (&mut self.buffer, self.width);
+        if overlays.memory {
+            let memory: Vec<u8> = (0..cpu.memory.size())
+                .map(|addr| cpu.memory.read(addr).unwrap_or(0))
+                .collect();
+            overlay::draw_memory(buffer, width, &memory);
+        }
+        if overlays.keypad {
+            overlay::draw_keypad(buffer, width, &cpu.key_state());
+        }
+        if overlays.opcode {
+            let pc = cpu.pc as usize;
+            let hi = cpu.memory.read(pc).unwrap_or(0) as u16;
+            let lo = cpu.memory.read(pc + 1).unwrap_or(0) as u16;
+            overlay::draw_opcode(buffer, width, cpu.pc, hi << 8 | lo);
+        }
+        if let Some(reg) = options.watch_reg {
+            overlay::draw_register(buffer, width, reg, cpu.V[reg]);
+        }
+        if options.visual_beep && cpu.beeping() {
+            overlay::draw_beep(buffer, width);
+        }
+    }
+}
+
+// Opens the emulator window, limited to ~60 updates per second or to the
+// draw rate when there is one
+fn open_window(
+    title: &str,
+    width: usize,
+    height: usize,
+    scale: Scale,
+    draw_rate: Option<u32>,
+) -> Result<Window, minifb::Error> {
+    let mut window = Window::new(
+        title,
+        width,
+        height,
+        WindowOptions {
+            //resize: true,
+            scale,
+            ..WindowOptions::default()
+        },
+    )?;
+
+    window.limit_update_rate(Some(display::update_interval(draw_rate)));
+
+    Ok(window)
+}
+
+// Something frames are drawn to, the window outside of tests
+trait Renderer: Sized {
+    fn draw(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<(), String>;
+}
+
+impl Renderer for Window {
+    fn draw(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<(), String> {
+        self.update_with_buffer(buffer, width, height)
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Draws a frame, replacing the renderer with a new one and drawing again
+// if that fails. Output is saved first in case the failure is fatal. Only
+// one replacement is made over a run, a failure after that ends it.
+fn present<R, S, O>(
+    renderer: &mut R,
+    screen: &Screen,
+    reopened: &mut bool,
+    save: S,
+    reopen: O,
+) -> Result<(), EmulateError>
+where
+    R: Renderer,
+    S: FnOnce() -> Result<(), EmulateError>,
+    O: FnOnce() -> Result<R, EmulateError>,
+{
+    let e = match renderer.draw(&screen.buffer, screen.width, screen.height) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    warn!("Unable to draw to the window: {}", e);
+    save()?;
+
+    if *reopened {
+        return Err(EmulateError::Window(e));
+    }
+    *reopened = true;
+
+    *renderer = reopen()?;
+    info!("Opened a new window");
+    renderer
+        .draw(&screen.buffer, screen.width, screen.height)
+        .map_err(EmulateError::Window)
+}
+
+// Lists the ROMs in dir in a window to choose one from with the arrow keys
+// or keypad. None if the window is closed first.
+fn pick_rom(dir: &str, options: &Options) -> Result<Option<String>, EmulateError> {
+    let roms = menu::list_roms(dir)?;
+    if roms.is_empty() {
+        return Err(EmulateError::NoRoms(dir.to_string()));
+    }
+    let mut menu = Menu::new(roms);
+
+    let (factor, _) = display::clamp_scale(
+        options.scale,
+        menu::WIDTH,
+        menu::HEIGHT,
+        display::ASSUMED_MONITOR,
+    );
+    let (scale, _) = display::window_scale(factor);
+    let mut window = open_window("Pick a ROM", menu::WIDTH, menu::HEIGHT, scale, None)
+        .map_err(|e| EmulateError::Window(e.to_string()))?;
+    let mut buffer = vec![0; menu::WIDTH * menu::HEIGHT];
+
+    while window.is_open() && !window.is_key_down(options.quit_key) {
+        let pressed = window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default();
+        for key in pressed {
+            if let Some(event) = menu::event(key, &options.keymap) {
+                if let Some(rom) = menu.handle(event) {
+                    return Ok(Some(rom.to_string()));
+                }
+            }
+        }
+
+        menu.draw(&mut buffer);
+        window
+            .update_with_buffer(&buffer, menu::WIDTH, menu::HEIGHT)
+            .map_err(|e| EmulateError::Window(e.to_string()))?;
+    }
+
+    Ok(None)
+}
+
+// Reads step mode commands from stdin on another thread, so the window
+// keeps responding while waiting for input. Reading stops at end of input.
+fn spawn_prompt() -> Receiver<Command> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut line = String::new();
+        while io::stdin().read_line(&mut line).unwrap_or(0) > 0 {
+            match debugger::parse(&line) {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    show_prompt();
+                }
+            }
+            line.clear();
+        }
+    });
+
+    receiver
+}
+
+fn show_prompt() {
+    print!("> ");
+    io::stdout().flush().unwrap();
+}
+
+fn main() {
+    // Errors are shown with their Display message rather than Debug
+    if let Err(e) = start() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn start() -> Result<(), EmulateError> {
+    let height: usize = 32;
+    let width: usize = 64;
+
+    let gfx: Vec<u32> = vec![0; width * height];
+
+    let options =
+        Options::load(std::env::args().skip(1).collect()).map_err(EmulateError::InvalidOptions)?;
+
+    logger::init(options.log_file.as_deref(), options.log_level).map_err(EmulateError::Logger)?;
+
+    let keymap = &options.keymap;
+
+    if options.show_keys {
+        print!("{}", keymap.render());
+        return Ok(());
+    }
+
+    #[cfg(feature = "zip")]
+    if let Some(path) = &options.list_archive {
+        for name in archive::list(&std::fs::read(path)?, path)? {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    if options.self_test {
+        let (report, passed) = self_test::run();
+        print!("{}", report);
+        if !passed {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(draws) = options.bench_draw {
+        print!("{}", bench::run(draws)?);
+        return Ok(());
+    }
+
+    if let Some(iterations) = options.fuzz {
+        let (report, passed) = fuzz::run(&options.config, iterations);
+        print!("{}", report);
+        if !passed {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut playlist = Playlist::new(options.roms.clone());
+
+    // Only a run in the window can be given its ROM from the menu
+    if let Some(dir) = &options.pick_from {
+        if options.opens_window() {
+            match pick_rom(dir, &options)? {
+                Some(rom) => playlist = Playlist::new(vec![rom]),
+                // Closed without choosing
+                None => return Ok(()),
+            }
+        } else if options.needs_rom() {
+            return Err(EmulateError::MissingRom);
+        }
+    }
+
+    if let Some(lang) = options.export_array {
+        print!(
+            "{}",
+            export::byte_array(&CPU::read_rom(playlist.current(), &options.config)?, lang)
+        );
+        return Ok(());
+    }
+
+    if options.dump_disasm {
+        let rom = CPU::read_rom(playlist.current(), &options.config)?;
+
+        // Ranges reaching outside the ROM are clamped to it
+        let whole = disasm::rom_range(&rom);
+        let range = match options.disasm_range {
+            Some((start, end)) => start.max(whole.start)..end.min(whole.end),
+            None => whole,
+        };
+
+        print!("{}", disasm::disassemble(&rom, range));
+        return Ok(());
+    }
+
+    let mut cpu = if playlist.is_empty() {
+        CPU::from_rom(&[], gfx, &options.config)?
+    } else {
+        CPU::initialize(playlist.current(), gfx, &options.config)?
+    };
+
+    if let Some(path) = &options.load_state {
+        state::load_state(&mut cpu, &std::fs::read_to_string(path)?)?;
+        info!("Resumed from {} at cycle {}", path, cpu.cycles);
+    }
+
+    let result = run(&mut cpu, &options, &mut playlist, keymap);
+
+    // Written whether the run finished or failed
+    if let Some(path) = &options.dump_state {
+        std::fs::write(path, state::to_json(&cpu))?;
+    }
+    log::logger().flush();
+
+    result
+}
+
+// Everything after the CPU is set up, in whichever mode was asked for
+fn run(
+    cpu: &mut CPU,
+    options: &Options,
+    playlist: &mut Playlist,
+    keymap: &Keymap,
+) -> Result<(), EmulateError> {
+    if let Some(path) = &options.trace {
+        let file = File::create(path)?;
+        cpu.trace = Some(BufWriter::new(file));
+    }
+    cpu.trace_format = options.trace_format;
+
+    if let Some(path) = &options.diff_trace {
+        let reference = std::fs::read_to_string(path)?;
+
+        if let Some(divergence) = trace::diff_trace(cpu, &reference)? {
+            return Err(EmulateError::TraceDiverged(
+                divergence.cycle,
+                divergence.expected,
+                divergence.actual,
+            ));
+        }
+
+        println!("Trace matched");
+        return Ok(());
+    }
+
+    // Read back from memory rather than FONTSET, so this shows what FX29
+    // will point at
+    if options.dump_fontset {
+        for digit in 0..16 {
+            let start = FONTSET_START + digit * 5;
+            let glyph = (start..start + 5)
+                .map(|addr| cpu.memory.read(addr))
+                .collect::<Result<Vec<u8>, EmulateError>>()?;
+
+            println!("{:X}", digit);
+            print!("{}", display::sprite_to_string(&glyph, 4));
+        }
+        return Ok(());
+    }
+
+    if let Some(quirks) = options.compare_quirks {
+        let config = Config {
+            quirks,
+            ..options.config.clone()
+        };
+        let mut second = CPU::initialize(playlist.current(), vec![0; cpu.gfx.len()], &config)?;
+
+        match trace::compare(cpu, &mut second, options.max_cycles)? {
+            Some(divergence) => {
+                println!("Diverged at cycle {}", divergence.cycle);
+                println!("First:  {}", divergence.expected);
+                println!("Second: {}", divergence.actual);
+            }
+            None => println!("No divergence within {} cycles", cpu.cycles),
+        }
+        return Ok(());
+    }
+
+    if options.quirk_test {
+        run_until_stable(
+            cpu,
+            quirk_test::STABLE_FRAMES,
+            options.max_cycles,
+            options.frame_budget(),
+        )?;
+
+        print!("{}", quirk_test::report(&cpu.gfx, cpu.width as usize));
+        return Ok(());
+    }
+
+    // Without a script no keys are ever pressed
+    let mut replay = match &options.replay {
+        Some(path) => {
+            let script = std::fs::read_to_string(path)?;
+            Some(ScriptedInput::parse(&script).map_err(EmulateError::InvalidReplay)?)
+        }
+        None => None,
+    };
+
+    if options.headless {
+        let input = replay.get_or_insert_with(ScriptedInput::default);
+        let end_frame = input.end_frame();
+        let mut reached = false;
+        run_headless(
+            cpu,
+            input,
+            options.max_cycles,
+            options.frame_budget(),
+            |cpu, frame| {
+                // A verified replay ends with its last key event
+                if options.replay_verify.is_some() && frame >= end_frame {
+                    return true;
+                }
+                if !options.checksum {
+                    return false;
+                }
+
+                let hash = display::checksum(&cpu.pack_display());
+                println!("{}:{:016X}", frame, hash);
+
+                reached = options.until_hash == Some(hash);
+                reached
+            },
+        )?;
+
+        if let Some(expected) = options.replay_verify {
+            let hash = display::checksum(&cpu.pack_display());
+            if hash != expected {
+                return Err(EmulateError::ReplayMismatch(expected, hash));
+            }
+            println!("Replay verified: {:016X}", hash);
+        }
+
+        if let Some(hash) = options.until_hash {
+            if !reached {
+                return Err(EmulateError::HashNotReached(hash));
+            }
+        }
+
+        if options.opcode_coverage {
+            print!("{}", profile::coverage(&cpu.profile));
+        }
+
+        if let Some(top) = options.profile_hotspots {
+            print!("{}", profile::hotspots(&cpu.hotspots, &*cpu.memory, top));
+        }
+
+        if options.count_idle {
+            println!("{}", profile::idle_ratio(cpu.idle_cycles, cpu.cycles));
+        }
+
+        if options.dump_gfx {
+            print!("{}", display::frame_to_string(&cpu.gfx, cpu.width as usize));
+        }
+        return Ok(());
+    }
+
+    if let Some(target) = options.run_to {
+        run_to(cpu, target, options.frame_budget())?;
+        println!("Stopped at cycle {}", cpu.cycles);
+        println!("{}", trace::format_state(cpu, TraceFormat::Default));
+    }
+
+    // Everything past this point needs a display. Non-interactive modes must
+    // return above so they work on machines without one.
+    let width = cpu.width as usize;
+    let height = cpu.height as usize;
+
+    let (factor, clamped) =
+        display::clamp_scale(options.scale, width, height, display::ASSUMED_MONITOR);
+    let (scale, warning) = display::window_scale(factor);
+    for warning in clamped.iter().chain(warning.iter()) {
+        println!("{}", warning);
+    }
+
+    let quit_key = keymap::key_name(options.quit_key);
+    let title = display::window_title(
+        &options.title,
+        playlist.current(),
+        0,
+        None,
+        false,
+        &quit_key,
+    );
+    let mut window = open_window(&title, width, height, scale, options.draw_rate)
+        .map_err(|e| EmulateError::Window(e.to_string()))?;
+
+    // A window that fails to draw is replaced once, a second failure ends
+    // the run
+    let mut reopened = false;
+
+    // Abstraction for displaying graphics
+    let mut screen = Screen::new(width, height);
+
+    let clock = SystemClock::new();
+    let mut last_frame = clock.now();
+
+    // Spaces instructions out in real time for --slow, the window still
+    // updates every frame in between
+    let mut throttle = options.slow.map(Throttle::new);
+
+    // Swap on and off colors, toggled with I
+    let mut invert = options.invert;
+
+    // Step mode takes commands from stdin, or single steps with Space
+    let commands = if options.step {
+        show_prompt();
+        Some(spawn_prompt())
+    } else {
+        None
+    };
+    let mut stepper = Stepper::default();
+    let mut debugger = Debugger::default();
+
+    // Stop running instructions, toggled with P. Step mode already waits
+    // for a command before each instruction so ignores this.
+    let mut paused = options.start_paused;
+
+    let mut overlays = Overlays::default();
+    let mut blur_pause = BlurPause::default();
+
+    // Frames shown so far, replay scripts are timed against this
+    let mut frame = 0;
+
+    let mut recorder = match &options.record_input {
+        Some(path) => Some(Recorder::create(path)?),
+        None => None,
+    };
+
+    // Frame and instruction counts when the title stats were last updated
+    let mut stats_start = clock.now();
+    let mut stats_frame = frame;
+    let mut stats_cycles = cpu.cycles;
+
+    // Stats shown in the title, kept so it can be redrawn in between
+    let mut title_fps = 0;
+    let mut title_cycles = None;
+    let mut title_waiting = false;
+
+    while window.is_open() && !window.is_key_down(options.quit_key) {
+        // Input: keys stay down for as long as they are held
+        cpu.set_keys(poll_keys(&window, keymap, &mut replay, frame))?;
+        if let Some(recorder) = &mut recorder {
+            recorder.record(frame, &cpu.key_state())?;
+        }
+        frame += 1;
+
+        debug!("Current Key Register: {:?}", cpu.key_state());
+
+        // Switch between ROMs given on the command line
+        if window.is_key_pressed(Key::PageDown, KeyRepeat::No) {
+            cpu.load(playlist.next(), &options.config)?;
+        } else if window.is_key_pressed(Key::PageUp, KeyRepeat::No) {
+            cpu.load(playlist.previous(), &options.config)?;
+        }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            paused = !paused;
+        }
+        if options.pause_on_blur {
+            blur_pause.update(window.is_active(), &mut paused);
+        }
+        if window.is_key_pressed(Key::I, KeyRepeat::No) {
+            invert = !invert;
+        }
+        overlays.toggle(&window);
+
+        // Update: run instructions and tick the timers by the time elapsed
+        if commands.is_some() && debugger.running {
+            let now = clock.now();
+            let stopped =
+                continue_frame(cpu, &mut debugger, options.frame_budget(), now - last_frame);
+            last_frame = now;
+
+            // Back to the prompt at whatever stopped the run
+            if stopped {
+                println!("{}", trace::format_state(cpu, TraceFormat::Default));
+                show_prompt();
+            }
+        } else if let Some(commands) = &commands {
+            // Time waiting for a command doesn't count towards the timers
+            last_frame = clock.now();
+
+            let command = if stepper.update(window.is_key_down(Key::Space)) {
+                Some(Command::Step)
+            } else {
+                commands.try_recv().ok()
+            };
+
+            if let Some(command) = command {
+                if !run_command(cpu, command, options.frame_budget(), &mut debugger)? {
+                    break;
+                }
+                if !debugger.running {
+                    println!("{}", trace::format_state(cpu, TraceFormat::Default));
+                    show_prompt();
+                }
+            }
+        } else if paused {
+            // Time spent paused doesn't count towards the timers
+            last_frame = clock.now();
+        } else {
+            let now = clock.now();
+            let budget = match &mut throttle {
+                Some(throttle) => FrameBudget {
+                    limit: throttle.due(now - last_frame),
+                    cycle_accurate: false,
+                },
+                None => options.frame_budget(),
+            };
+            run_frame(cpu, budget, now - last_frame)?;
+            last_frame = now;
+        }
+
+        // Render
+        screen.draw(cpu, options, invert, &overlays);
+
+        // Refresh the title with stats measured over the last second
+        let elapsed = clock.elapsed(stats_start);
+        if elapsed >= Duration::from_secs(1) {
+            let frames = frame - stats_frame;
+            title_fps = (frames as f64 / elapsed.as_secs_f64()).round() as u32;
+
+            // Loading a ROM resets the instruction count
+            title_cycles = if options.verbose {
+                Some(cpu.cycles.saturating_sub(stats_cycles) / frames.max(1))
+            } else {
+                None
+            };
+
+            stats_start = clock.now();
+            stats_frame = frame;
+            stats_cycles = cpu.cycles;
+        }
+
+        // Also redrawn straight away when the ROM starts or stops waiting
+        if stats_frame == frame || cpu.is_waiting_for_key() != title_waiting {
+            title_waiting = cpu.is_waiting_for_key();
+            window.set_title(&display::window_title(
+                &options.title,
+                playlist.current(),
+                title_fps,
+                title_cycles,
+                title_waiting,
+                &quit_key,
+            ));
+        }
+
+        if display::should_render(frame, options.frameskip) {
+            present(
+                &mut window,
+                &screen,
+                &mut reopened,
+                || {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.flush()?;
+                    }
+                    if let Some(trace) = cpu.trace.as_mut() {
+                        trace.flush()?;
+                    }
+                    Ok(())
+                },
+                || {
+                    open_window(&title, width, height, scale, options.draw_rate)
+                        .map_err(|e| EmulateError::Window(e.to_string()))
+                },
+            )?;
+        } else {
+            // Still collect input on frames that aren't drawn
+            window.update();
+        }
+    }
+
+    if let Some(recorder) = &mut recorder {
+        recorder.flush()?;
+    }
+
+    if options.count_idle {
+        println!("{}", profile::idle_ratio(cpu.idle_cycles, cpu.cycles));
+    }
+
+    // Window was closed normally
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(rom: &[u8]) -> CPU {
+        CPU::from_rom(rom, vec![0; 64 * 32], &Config::default()).unwrap()
+    }
+
+    // Config set up by command line flags
+    fn config(args: &[&str]) -> Config {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+            .unwrap()
+            .config
+    }
+
+    // Runs one instruction a frame, without input, until the ROM halts
+    fn run_headless(cpu: &mut CPU, max_cycles: u64) -> Result<(), EmulateError> {
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        super::run_headless(
+            cpu,
+            &mut ScriptedInput::default(),
+            max_cycles,
+            budget,
+            |_, _| false,
+        )
+    }
+
+    #[test]
+    fn large_rom_loads_into_xochip_memory() {
+        let rom = vec![0; 0x2000];
+        let config = config(&["--compat", "xochip"]);
+        let cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config).unwrap();
+        assert_eq!(cpu.memory.size(), memory::XOCHIP_SIZE);
+    }
+
+    #[test]
+    fn large_rom_is_refused_by_classic_memory() {
+        let rom = vec![0; 0x2000];
+        let result = CPU::from_rom(&rom, vec![0; 64 * 32], &Config::default());
+        assert!(matches!(
+            result,
+            Err(EmulateError::RomTooLarge(0x2000, 0xE00))
+        ));
+    }
+
+    // Values CXNN puts in V0 over a seeded run of a loop over it
+    fn random_values(nn: u8, count: usize) -> Vec<u8> {
+        let config = Config {
+            seed: Some(1),
+            ..Config::default()
+        };
+        let mut cpu = CPU::from_rom(&[0xC0, nn, 0x12, 0x00], vec![0; 64 * 32], &config).unwrap();
+
+        (0..count)
+            .map(|_| {
+                cpu.emulate_cycle().unwrap();
+                let value = cpu.V[0];
+                cpu.emulate_cycle().unwrap();
+                value
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cxnn_reaches_every_byte() {
+        let mut seen = [false; 256];
+        for value in random_values(0xFF, 10000) {
+            seen[value as usize] = true;
+        }
+        assert!(seen[0xFF]);
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn cxnn_masks_with_nn() {
+        let values = random_values(0x0F, 1000);
+        assert!(values.iter().all(|value| *value <= 0x0F));
+        assert!(values.contains(&0x0F));
+    }
+
+    #[test]
+    fn redrawing_a_sprite_reports_a_collision() {
+        // Draws the 0 glyph twice at the top left
+        let mut cpu = cpu(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05]);
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!(cpu.V[0xF], 0);
+        assert_eq!(cpu.gfx[0], 1);
+
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0xF], 1);
+        assert!(cpu.gfx.iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn set_key_presses_and_releases_single_keys() {
+        let mut cpu = cpu(&[]);
+        cpu.set_key(0x0, true).unwrap();
+        cpu.set_key(0x7, true).unwrap();
+        cpu.set_key(0xF, true).unwrap();
+        cpu.set_key(0x7, false).unwrap();
+
+        let mut expected = [false; 16];
+        expected[0x0] = true;
+        expected[0xF] = true;
+        assert_eq!(cpu.key_state(), expected);
+    }
+
+    #[test]
+    fn set_key_refuses_keys_past_f() {
+        let mut cpu = cpu(&[]);
+        assert_eq!(cpu.set_key(16, true), Err(EmulateError::InvalidKey(16)));
+        assert_eq!(cpu.key_state(), [false; 16]);
+    }
+
+    #[test]
+    fn held_key_completes_fx0a_once_until_released() {
+        // F1 0A, F2 0A, then loop
+        let mut cpu = cpu(&[0xF1, 0x0A, 0xF2, 0x0A, 0x12, 0x04]);
+        cpu.set_key(0x7, true).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[1], 0x7);
+
+        cpu.emulate_cycle().unwrap();
+        assert!(cpu.is_waiting_for_key());
+
+        cpu.set_key(0x7, false).unwrap();
+        cpu.set_key(0x7, true).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[2], 0x7);
+        assert!(!cpu.is_waiting_for_key());
+    }
+
+    #[test]
+    fn fx0a_on_release_completes_when_the_key_goes_up() {
+        let config = Config {
+            quirks: Quirks {
+                fx0a_on_release: true,
+                ..Quirks::default()
+            },
+            ..Config::default()
+        };
+        // F3 0A, then loop
+        let mut cpu = CPU::from_rom(&[0xF3, 0x0A, 0x12, 0x02], vec![0; 64 * 32], &config).unwrap();
+        let mut input = ScriptedInput::parse("0 5 down\n2 5 up").unwrap();
+
+        for frame in 0..2 {
+            cpu.set_keys(input.poll(frame)).unwrap();
+            cpu.emulate_cycle().unwrap();
+            assert!(cpu.is_waiting_for_key());
+        }
+        assert_eq!(cpu.V[3], 0);
+
+        cpu.set_keys(input.poll(2)).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.V[3], 5);
+    }
+
+    #[test]
+    fn memory_fill_covers_unused_memory() {
+        let config = Config {
+            mem_fill: MemFill::Ones,
+            ..Config::default()
+        };
+        let cpu = CPU::from_rom(&[0x12, 0x00], vec![0; 64 * 32], &config).unwrap();
+
+        assert_eq!(cpu.memory.read(0x202), Ok(0xFF));
+        assert_eq!(cpu.memory.read(0xFFF), Ok(0xFF));
+
+        // The ROM and fontset are still loaded over it
+        assert_eq!(cpu.memory.read(0x200), Ok(0x12));
+        assert_eq!(cpu.memory.read(0x000), Ok(0xFF));
+        assert_eq!(cpu.memory.read(FONTSET_START), Ok(0xF0));
+    }
+
+    #[test]
+    fn headless_run_succeeds_once_the_rom_halts() {
+        // 60 01, then a jump to itself
+        let mut cpu = cpu(&[0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(run_headless(&mut cpu, 100), Ok(()));
+        assert!(cpu.halted);
+        assert_eq!(cpu.V[0], 1);
+    }
+
+    #[test]
+    fn coverage_lists_the_opcodes_a_rom_ran() {
+        // 60 05, A2 10, then a jump to itself
+        let mut cpu = cpu(&[0x60, 0x05, 0xA2, 0x10, 0x12, 0x04]);
+        run_headless(&mut cpu, 100).unwrap();
+
+        let report = profile::coverage(&cpu.profile);
+        let mut lines = report.lines();
+        let executed = format!("Executed (3/{}): 1NNN 6XNN ANNN", profile::OPCODES.len());
+        assert_eq!(lines.next(), Some(executed.as_str()));
+        let missed = lines.next().unwrap();
+        assert!(missed.starts_with("Never hit: 00CN 00E0 00EE"));
+        assert!(!missed.contains("6XNN"));
+    }
+
+    #[test]
+    fn hires_draws_16x16_sprites_until_switched_back() {
+        let mut rom = vec![
+            0x00, 0xFF, // hires
+            0xA2, 0x0C, // I = sprite
+            0xD0, 0x00, // 16x16 sprite at 0, 0
+            0x00, 0xFE, // lores
+            0xD0, 0x00, // nothing in lores
+            0x12, 0x0A,
+        ];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let mut cpu = cpu(&rom);
+
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!((cpu.width, cpu.height), (128, 64));
+        assert_eq!(cpu.gfx.len(), 128 * 64);
+        assert_eq!(cpu.gfx.iter().filter(|pixel| **pixel == 1).count(), 16 * 16);
+        assert_eq!(cpu.gfx[15 + 15 * 128], 1);
+        assert_eq!(cpu.gfx[16], 0);
+
+        for _ in 0..2 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!((cpu.width, cpu.height), (64, 32));
+        assert_eq!(cpu.gfx.len(), 64 * 32);
+        assert!(cpu.gfx.iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn seventeenth_nested_call_overflows_the_stack() {
+        // Calls itself forever
+        let mut cpu = cpu(&[0x22, 0x00]);
+        for _ in 0..16 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!(cpu.emulate_cycle(), Err(EmulateError::StackOverflow));
+    }
+
+    #[test]
+    fn dumped_display_shows_the_drawn_glyph() {
+        // Draws the 0 glyph at the top left, then halts
+        let mut cpu = cpu(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+        run_headless(&mut cpu, 100).unwrap();
+
+        let dump = display::frame_to_string(&cpu.gfx, cpu.width as usize);
+        let rows: Vec<&str> = dump.lines().collect();
+        assert_eq!(rows.len(), 32);
+        assert!(rows[0].starts_with("####...."));
+        assert!(rows[1].starts_with("#..#...."));
+        assert!(rows[4].starts_with("####...."));
+        assert_eq!(rows[5], ".".repeat(64));
+    }
+
+    #[test]
+    fn endless_loop_hits_the_cycle_limit() {
+        // 70 01, then a jump back to it
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        assert_eq!(
+            run_headless(&mut cpu, 50),
+            Err(EmulateError::CycleLimitExceeded(50))
+        );
+        assert_eq!(cpu.cycles, 50);
+    }
+
+    #[test]
+    fn pokes_land_after_the_rom_is_loaded() {
+        let config = config(&["--poke", "0x202=0xAB", "--poke", "0x300=7"]);
+        let cpu = CPU::from_rom(&[0x12, 0x00, 0x00], vec![0; 64 * 32], &config).unwrap();
+
+        assert_eq!(cpu.memory.read(0x202), Ok(0xAB));
+        assert_eq!(cpu.memory.read(0x300), Ok(0x07));
+        assert_eq!(cpu.memory.read(0x200), Ok(0x12));
+    }
+
+    #[test]
+    fn pokes_into_the_fontset_are_refused() {
+        let config = config(&["--poke", "0x50=0"]);
+        let result = CPU::from_rom(&[], vec![0; 64 * 32], &config);
+        assert!(matches!(result, Err(EmulateError::ProtectedWrite(0x50))));
+    }
+
+    // Row 0 of the display after drawing a full byte at X=62
+    fn row_after_drawing_at_62(clip: bool) -> Vec<u32> {
+        let config = Config {
+            quirks: Quirks {
+                clip_sprites: clip,
+                ..Quirks::default()
+            },
+            ..Config::default()
+        };
+        let rom = [0x60, 0x3E, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xFF];
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config).unwrap();
+        for _ in 0..4 {
+            cpu.emulate_cycle().unwrap();
+        }
+        cpu.gfx[..64].to_vec()
+    }
+
+    #[test]
+    fn sprite_past_the_right_edge_wraps_or_clips() {
+        let wrapped = row_after_drawing_at_62(false);
+        let lit: Vec<usize> = (0..64).filter(|x| wrapped[*x] == 1).collect();
+        assert_eq!(lit, vec![0, 1, 2, 3, 4, 5, 62, 63]);
+
+        let clipped = row_after_drawing_at_62(true);
+        let lit: Vec<usize> = (0..64).filter(|x| clipped[*x] == 1).collect();
+        assert_eq!(lit, vec![62, 63]);
+    }
+
+    #[test]
+    fn frame_command_runs_one_frame_of_instructions() {
+        // A run of 60 01 loads
+        let mut cpu = cpu(&[0x60, 0x01].repeat(20));
+        let budget = FrameBudget {
+            limit: 7,
+            cycle_accurate: false,
+        };
+        let mut debugger = Debugger::default();
+
+        run_command(&mut cpu, Command::Frame, budget, &mut debugger).unwrap();
+        assert_eq!(cpu.pc, 0x200 + 7 * 2);
+
+        run_command(&mut cpu, Command::Step, budget, &mut debugger).unwrap();
+        assert_eq!(cpu.pc, 0x200 + 8 * 2);
+    }
+
+    #[test]
+    fn fx29_points_at_the_glyph_for_the_low_nibble() {
+        // 60 0A, F0 29, 60 FA, F0 29
+        let mut cpu = cpu(&[0x60, 0x0A, 0xF0, 0x29, 0x60, 0xFA, 0xF0, 0x29]);
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.I as usize, FONTSET_START + 0xA * 5);
+        assert_eq!(cpu.memory.read(cpu.I as usize), Ok(0xF0));
+
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.I as usize, FONTSET_START + 0xA * 5);
+    }
+
+    #[test]
+    fn run_to_stops_at_the_given_cycle() {
+        // 70 01, then a jump back to it
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        let budget = FrameBudget {
+            limit: 7,
+            cycle_accurate: false,
+        };
+
+        // Stops partway through a frame
+        run_to(&mut cpu, 25, budget).unwrap();
+        assert_eq!(cpu.cycles, 25);
+        assert_eq!(cpu.V[0], 13);
+    }
+
+    #[test]
+    fn timers_tick_once_per_60th_of_a_second() {
+        let mut cpu = cpu(&[]);
+        cpu.delay_timer = 10;
+        cpu.sound_timer = 2;
+
+        cpu.tick_timers(FRAME * 3 + FRAME / 2);
+        assert_eq!(cpu.delay_timer, 7);
+        assert_eq!(cpu.sound_timer, 0);
+
+        // The leftover half frame carries over
+        cpu.tick_timers(FRAME / 2);
+        assert_eq!(cpu.delay_timer, 6);
+        cpu.tick_timers(FRAME / 2);
+        assert_eq!(cpu.delay_timer, 6);
+    }
+
+    #[test]
+    fn set_command_writes_memory() {
+        let mut cpu = cpu(&[]);
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        let command = debugger::parse("set 0x300 0xAB").unwrap();
+
+        run_command(&mut cpu, command, budget, &mut Debugger::default()).unwrap();
+        assert_eq!(cpu.memory.read(0x300), Ok(0xAB));
+    }
+
+    #[test]
+    fn skip_if_skips_only_when_the_condition_holds() {
+        let mut cpu = cpu(&[0x00, 0xE0, 0x00, 0xE0]);
+        cpu.skip_if(false).unwrap();
+        assert_eq!(cpu.pc, 0x202);
+
+        cpu.pc = 0x200;
+        cpu.skip_if(true).unwrap();
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn skip_opcodes_compare_against_nn() {
+        // 60 2A, 30 2A (skips), 00 E0, 40 2A (doesn't skip)
+        let mut cpu = cpu(&[0x60, 0x2A, 0x30, 0x2A, 0x00, 0xE0, 0x40, 0x2A]);
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.pc, 0x208);
+    }
+
+    #[test]
+    fn draw_uses_up_its_share_of_a_cycle_accurate_frame() {
+        // D0 05, then loads
+        let mut rom = vec![0xD0, 0x05];
+        rom.extend([0x60, 0x01].repeat(10));
+        let mut cpu = cpu(&rom);
+        let budget = FrameBudget {
+            limit: 200,
+            cycle_accurate: true,
+        };
+
+        // 192 cycles for the draw leaves room for two 6 cycle loads
+        run_frame(&mut cpu, budget, FRAME).unwrap();
+        assert_eq!(cpu.cycles, 3);
+    }
+
+    #[test]
+    fn packed_display_round_trips() {
+        let mut cpu = cpu(&[]);
+        // Checkerboard on the first row, a diagonal below it
+        for x in (0..64).step_by(2) {
+            cpu.gfx[x] = 1;
+        }
+        for y in 1..32 {
+            cpu.gfx[y + y * 64] = 1;
+        }
+
+        let packed = cpu.pack_display();
+        assert_eq!(packed.len(), 64 * 32 / 8);
+        assert_eq!(packed[0], 0b1010_1010);
+        assert_eq!(packed[8], 0b0100_0000);
+
+        let mut restored = self::cpu(&[]);
+        restored.unpack_display(&packed).unwrap();
+        assert_eq!(restored.gfx, cpu.gfx);
+    }
+
+    #[test]
+    fn packed_display_of_the_wrong_length_is_refused() {
+        let mut cpu = cpu(&[]);
+        assert_eq!(
+            cpu.unpack_display(&[0; 10]),
+            Err(EmulateError::InvalidDisplayData(10))
+        );
+    }
+
+    #[test]
+    fn fetch_at_the_last_byte_of_memory_is_out_of_bounds() {
+        let mut cpu = cpu(&[]);
+        cpu.pc = 0xFFF;
+        assert_eq!(cpu.emulate_cycle(), Err(EmulateError::OutOfBounds(0xFFF)));
+
+        // The last whole opcode still runs
+        cpu.memory.write(0xFFE, 0x60).unwrap();
+        cpu.memory.write(0xFFF, 0x01).unwrap();
+        cpu.pc = 0xFFE;
+        assert_eq!(cpu.emulate_cycle(), Ok(()));
+        assert_eq!(cpu.V[0], 1);
+    }
+
+    #[test]
+    fn logic_opcodes_clear_vf_only_with_the_quirk() {
+        for op in [0x11, 0x12, 0x13].iter() {
+            for reset in [false, true].iter() {
+                let config = Config {
+                    quirks: Quirks {
+                        logic_resets_vf: *reset,
+                        ..Quirks::default()
+                    },
+                    ..Config::default()
+                };
+                // VF = 5, V0 = 0C, V1 = 0A, then 8X1N
+                let rom = [0x6F, 0x05, 0x60, 0x0C, 0x61, 0x0A, 0x80, *op];
+                let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config).unwrap();
+                for _ in 0..4 {
+                    cpu.emulate_cycle().unwrap();
+                }
+
+                let expected = if *reset { 0 } else { 5 };
+                assert_eq!(cpu.V[0xF], expected, "80{:02X}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn register_opcodes_handle_0x00_and_0xff() {
+        let opcodes: [u16; 20] = [
+            0x3000, 0x4000, 0x5010, 0x7001, 0x8010, 0x8011, 0x8012, 0x8013, 0x8014, 0x8015, 0x8016,
+            0x801E, 0x9010, 0xC0FF, 0xE09E, 0xE0A1, 0xF01E, 0xF029, 0xF033, 0xF015,
+        ];
+
+        for value in [0x00, 0xFF].iter() {
+            for opcode in opcodes.iter() {
+                // V0 = V1 = value, I = 0x300, then the opcode
+                let rom = [
+                    0x60,
+                    *value,
+                    0x61,
+                    *value,
+                    0xA3,
+                    0x00,
+                    (opcode >> 8) as u8,
+                    *opcode as u8,
+                ];
+                let mut cpu = cpu(&rom);
+                for _ in 0..3 {
+                    cpu.emulate_cycle().unwrap();
+                }
+
+                // None of them overflow or fail at either end of the range
+                if let Err(e) = cpu.emulate_cycle() {
+                    panic!("{:04X} with {:02X}: {}", opcode, value, e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_wraps_and_flags_overflow() {
+        // V0 = FF, V1 = 01, 80 14
+        let mut cpu = cpu(&[0x60, 0xFF, 0x61, 0x01, 0x80, 0x14]);
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x00, 1));
+
+        // V0 = 00, V1 = 01, 80 15
+        let mut cpu = self::cpu(&[0x60, 0x00, 0x61, 0x01, 0x80, 0x15]);
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0xFF, 0));
+
+        // 7XNN wraps without touching VF: VF = 7, V0 = FF, 70 02
+        let mut cpu = self::cpu(&[0x6F, 0x07, 0x60, 0xFF, 0x70, 0x02]);
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x01, 7));
+    }
+
+    #[test]
+    fn start_paused_stays_paused_through_focus_changes() {
+        let options = Options::parse(vec![String::from("--start-paused")].into_iter()).unwrap();
+        let mut paused = options.start_paused;
+        assert!(paused);
+
+        // Only a pause the blur caused is undone when focus comes back
+        let mut blur_pause = BlurPause::default();
+        blur_pause.update(false, &mut paused);
+        blur_pause.update(true, &mut paused);
+        assert!(paused);
+    }
+
+    #[test]
+    fn enlarged_stack_allows_deeper_calls() {
+        let config = config(&["--stack-depth", "32"]);
+        // Calls itself forever
+        let mut cpu = CPU::from_rom(&[0x22, 0x00], vec![0; 64 * 32], &config).unwrap();
+        for _ in 0..32 {
+            cpu.emulate_cycle().unwrap();
+        }
+        assert_eq!(cpu.sp, 32);
+        assert_eq!(cpu.emulate_cycle(), Err(EmulateError::StackOverflow));
+    }
+
+    #[test]
+    fn strict_mode_refuses_a_shift_without_its_quirk() {
+        // 80 16 at 0x200
+        let rom = [0x80, 0x16];
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config(&["--strict"])).unwrap();
+        assert_eq!(
+            cpu.emulate_cycle(),
+            Err(EmulateError::AmbiguousOpcode(0x200, 0x8016))
+        );
+
+        // Choosing the quirk, or a preset, settles it
+        for args in [
+            &["--strict", "--no-quirk", "shift-uses-vx"][..],
+            &["--strict", "--compat", "schip"][..],
+        ]
+        .iter()
+        {
+            let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config(args)).unwrap();
+            assert_eq!(cpu.emulate_cycle(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn no_fontset_leaves_the_font_region_to_the_rom() {
+        let cpu = cpu(&[]);
+        assert_eq!(cpu.memory.read(FONTSET_START).unwrap(), FONTSET[0]);
+
+        let config = config(&["--no-fontset", "--poke", "0x50=0xAA"]);
+        let cpu = CPU::from_rom(&[], vec![0; 64 * 32], &config).unwrap();
+        assert_eq!(cpu.memory.read(FONTSET_START).unwrap(), 0xAA);
+        for addr in FONTSET_START + 1..FONTSET_START + FONTSET.len() {
+            assert_eq!(cpu.memory.read(addr).unwrap(), 0);
+        }
+    }
+
+    // Column of the one lit pixel in row 0 after scrolling it right from
+    // column 0 in low resolution
+    fn scrolled_column(args: &[&str]) -> usize {
+        let mut cpu = CPU::from_rom(&[0x00, 0xFB], vec![0; 64 * 32], &config(args)).unwrap();
+        cpu.gfx[0] = 1;
+        cpu.emulate_cycle().unwrap();
+        cpu.gfx.iter().position(|pixel| *pixel == 1).unwrap()
+    }
+
+    #[test]
+    fn half_pixel_scroll_halves_lores_scrolls() {
+        assert_eq!(scrolled_column(&["--quirk", "half-pixel-scroll"]), 2);
+        assert_eq!(scrolled_column(&["--no-quirk", "half-pixel-scroll"]), 4);
+    }
+
+    #[test]
+    fn display_checksum_changes_with_a_draw() {
+        // Draw the 0 glyph at (0, 0)
+        let mut cpu = cpu(&[0xA0, 0x50, 0xD0, 0x05]);
+        let before = display::checksum(&cpu.pack_display());
+        assert_eq!(before, display::checksum(&cpu.pack_display()));
+
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_ne!(before, display::checksum(&cpu.pack_display()));
+    }
+
+    // Renderer that fails its first `failures` draws
+    struct FlakyRenderer {
+        failures: u32,
+        draws: u32,
+    }
+
+    impl Renderer for FlakyRenderer {
+        fn draw(&mut self, _: &[u32], _: usize, _: usize) -> Result<(), String> {
+            self.draws += 1;
+            if self.draws <= self.failures {
+                return Err(String::from("lost the display"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_draw_reopens_the_window_once() {
+        let screen = Screen::new(64, 32);
+        let mut renderer = FlakyRenderer {
+            failures: 1,
+            draws: 0,
+        };
+        let mut reopened = false;
+        let mut saved = false;
+
+        let result = present(
+            &mut renderer,
+            &screen,
+            &mut reopened,
+            || {
+                saved = true;
+                Ok(())
+            },
+            || {
+                Ok(FlakyRenderer {
+                    failures: 0,
+                    draws: 0,
+                })
+            },
+        );
+        assert!(result.is_ok());
+        assert!(saved && reopened);
+        // The frame was drawn to the new renderer
+        assert_eq!(renderer.draws, 1);
+
+        // A failure after the window was replaced ends the run
+        renderer.failures = 2;
+        let result = present(
+            &mut renderer,
+            &screen,
+            &mut reopened,
+            || Ok(()),
+            || panic!("only one new window is opened"),
+        );
+        assert!(matches!(result, Err(EmulateError::Window(_))));
+    }
+
+    #[test]
+    fn clearregs_zeroes_only_the_range() {
+        let mut cpu = cpu(&[]);
+        cpu.V = [0xFF; 16];
+        let command = debugger::parse("clearregs 2 4").unwrap();
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        run_command(&mut cpu, command, budget, &mut Debugger::default()).unwrap();
+
+        let cleared: Vec<usize> = (0..16).filter(|reg| cpu.V[*reg] == 0).collect();
+        assert_eq!(cleared, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn hotspots_lead_with_the_loop() {
+        // V0 counts to 5 in a loop at 202-206, then halts at 208
+        let mut cpu = cpu(&[0x60, 0x00, 0x70, 0x01, 0x30, 0x05, 0x12, 0x02, 0x12, 0x08]);
+        run_headless(&mut cpu, 100).unwrap();
+
+        let listing = profile::hotspots(&cpu.hotspots, &*cpu.memory, 3);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("202           5  7001"), "{}", listing);
+        assert!(lines[1].starts_with("204           5  3005"), "{}", listing);
+        assert!(lines[2].starts_with("206           4  1202"), "{}", listing);
+    }
+
+    #[test]
+    fn hex_roms_parse_to_bytes() {
+        assert_eq!(
+            CPU::parse_hex("60 0A D0 15"),
+            Ok(vec![0x60, 0x0A, 0xD0, 0x15])
+        );
+        assert_eq!(
+            CPU::parse_hex("60 0a\nd0\t15\n"),
+            Ok(vec![0x60, 0x0A, 0xD0, 0x15])
+        );
+        assert_eq!(
+            CPU::parse_hex("60 0A0"),
+            Err(EmulateError::InvalidHex(String::from("0A0")))
+        );
+        assert_eq!(
+            CPU::parse_hex("6Z"),
+            Err(EmulateError::InvalidHex(String::from("6Z")))
+        );
+    }
+
+    #[test]
+    fn quirk_command_applies_to_the_next_opcode() {
+        // 60 03, 61 04, then 80 16 twice
+        let mut cpu = cpu(&[0x60, 0x03, 0x61, 0x04, 0x80, 0x16, 0x80, 0x16]);
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        let mut debugger = Debugger::default();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        let command = debugger::parse("quirk shift-uses-vx on").unwrap();
+        run_command(&mut cpu, command, budget, &mut debugger).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0], 0x03 >> 1);
+
+        let command = debugger::parse("quirk shift-uses-vx off").unwrap();
+        run_command(&mut cpu, command, budget, &mut debugger).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0], 0x04 >> 1);
+    }
+
+    #[test]
+    fn zero_glyph_renders_as_a_box() {
+        let cpu = cpu(&[]);
+        let glyph: Vec<u8> = (FONTSET_START..FONTSET_START + 5)
+            .map(|addr| cpu.memory.read(addr).unwrap())
+            .collect();
+
+        assert_eq!(
+            display::sprite_to_string(&glyph, 4),
+            "####\n#..#\n#..#\n#..#\n####\n"
+        );
+    }
+
+    #[test]
+    fn beeps_while_the_sound_timer_runs() {
+        // 60 02, F0 18
+        let mut cpu = cpu(&[0x60, 0x02, 0xF0, 0x18]);
+        cpu.emulate_cycle().unwrap();
+        assert!(!cpu.beeping());
+
+        cpu.emulate_cycle().unwrap();
+        assert!(cpu.beeping());
+        cpu.tick_timers(FRAME);
+        assert!(cpu.beeping());
+        cpu.tick_timers(FRAME);
+        assert!(!cpu.beeping());
+    }
+
+    #[test]
+    fn run_frame_spends_the_budget_then_ticks_timers() {
+        // A run of 60 01 loads, 6 VIP machine cycles each
+        let mut cpu = cpu(&[0x60, 0x01].repeat(40));
+        cpu.delay_timer = 5;
+        let counted = FrameBudget {
+            limit: 10,
+            cycle_accurate: false,
+        };
+        run_frame(&mut cpu, counted, FRAME).unwrap();
+        assert_eq!(cpu.cycles, 10);
+        assert_eq!(cpu.delay_timer, 4);
+
+        // Stops once the machine cycles used reach the limit
+        let accurate = FrameBudget {
+            limit: 20,
+            cycle_accurate: true,
+        };
+        run_frame(&mut cpu, accurate, FRAME).unwrap();
+        assert_eq!(cpu.cycles, 14);
+        assert_eq!(cpu.delay_timer, 3);
+    }
+
+    #[test]
+    fn losing_focus_pauses_until_it_returns() {
+        let mut paused = false;
+        let mut blur_pause = BlurPause::default();
+
+        blur_pause.update(true, &mut paused);
+        assert!(!paused);
+        blur_pause.update(false, &mut paused);
+        assert!(paused);
+        blur_pause.update(false, &mut paused);
+        assert!(paused);
+        blur_pause.update(true, &mut paused);
+        assert!(!paused);
+    }
+
+    #[test]
+    fn sprite_rows_past_the_end_of_memory_are_blank() {
+        // AF FE, D0 05
+        let rom = [0xAF, 0xFE, 0xD0, 0x05];
+        let mut cpu = cpu(&rom);
+        cpu.memory.write(0xFFE, 0xFF).unwrap();
+        cpu.memory.write(0xFFF, 0x81).unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        let lit: Vec<usize> = (0..64 * 5).filter(|index| cpu.gfx[*index] == 1).collect();
+        assert_eq!(lit, vec![0, 1, 2, 3, 4, 5, 6, 7, 64, 71]);
+
+        let config = Config {
+            strict: true,
+            ..Config::default()
+        };
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.emulate_cycle(), Err(EmulateError::OutOfBounds(0x1000)));
+    }
+
+    #[test]
+    fn seeded_random_registers() {
+        let random = config(&["--rand-regs", "--seed", "42"]);
+        let cpu = CPU::from_rom(&[], vec![0; 64 * 32], &random).unwrap();
+        assert_eq!(
+            cpu.V,
+            [
+                0x78, 0x11, 0x99, 0xF9, 0xD5, 0x5D, 0x18, 0xE8, 0x55, 0xA7, 0xAA, 0x84, 0xC5, 0xE4,
+                0xB4, 0x0D
+            ]
+        );
+
+        // Registers still start at zero by default
+        let cpu = CPU::from_rom(&[], vec![0; 64 * 32], &config(&["--seed", "42"])).unwrap();
+        assert_eq!(cpu.V, [0; 16]);
+    }
+
+    #[test]
+    fn continue_stops_at_a_breakpoint() {
+        // Counts V0 up forever in a loop at 200-202
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        let budget = FrameBudget {
+            limit: 5,
+            cycle_accurate: false,
+        };
+        let mut debugger = Debugger::default();
+        debugger.breakpoints.push(0x202);
+
+        run_command(&mut cpu, Command::Continue, budget, &mut debugger).unwrap();
+        assert!(debugger.running);
+        assert!(continue_frame(&mut cpu, &mut debugger, budget, FRAME));
+        assert!(!debugger.running);
+        assert_eq!((cpu.pc, cpu.V[0]), (0x202, 1));
+
+        // Continuing again runs the loop once more
+        run_command(&mut cpu, Command::Continue, budget, &mut debugger).unwrap();
+        assert!(continue_frame(&mut cpu, &mut debugger, budget, FRAME));
+        assert_eq!((cpu.pc, cpu.V[0]), (0x202, 2));
+    }
+
+    #[test]
+    fn waits_for_a_key_until_one_is_pressed() {
+        // F0 0A, then loop
+        let mut cpu = cpu(&[0xF0, 0x0A, 0x12, 0x02]);
+        assert!(!cpu.is_waiting_for_key());
+
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+            assert!(cpu.is_waiting_for_key());
+            assert_eq!(cpu.pc, 0x200);
+        }
+
+        cpu.set_key(0xB, true).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!((cpu.pc, cpu.V[0]), (0x202, 0xB));
+    }
+
+    // CPU that has run the unknown opcode 8XY8 at 0x200 under a policy
+    fn after_unknown_opcode(policy: &str) -> (CPU, Result<(), EmulateError>) {
+        let config = config(&["--on-unknown", policy]);
+        let mut cpu = CPU::from_rom(&[0x80, 0x08, 0x60, 0x01], vec![0; 64 * 32], &config).unwrap();
+        let result = cpu.emulate_cycle();
+        (cpu, result)
+    }
+
+    #[test]
+    fn unknown_opcode_errors_by_default() {
+        let (cpu, result) = after_unknown_opcode("error");
+        assert_eq!(result, Err(EmulateError::UnknownOpcode(0x200, 0x8008)));
+        assert!(!cpu.halted);
+    }
+
+    #[test]
+    fn unknown_opcode_can_halt() {
+        let (mut cpu, result) = after_unknown_opcode("halt");
+        assert_eq!(result, Ok(()));
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0x200);
+
+        // Stays halted on the opcode
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn unknown_opcode_can_be_skipped() {
+        let (mut cpu, result) = after_unknown_opcode("skip");
+        assert_eq!(result, Ok(()));
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x202);
+
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0], 1);
+    }
+
+    #[test]
+    fn dirty_blit_matches_a_full_blit() {
+        // Draws, erases, moves and clears the 0 glyph
+        let mut cpu = cpu(&[
+            0xA0, 0x50, 0x60, 0x05, 0x61, 0x0A, 0xD0, 0x15, 0xD0, 0x15, 0x70, 0x08, 0xD0, 0x15,
+            0x00, 0xE0, 0xD0, 0x15,
+        ]);
+        let options = Options::default();
+        let mut screen = Screen::new(64, 32);
+
+        for _ in 0..9 {
+            cpu.emulate_cycle().unwrap();
+            screen.draw(&mut cpu, &options, false, &Overlays::default());
+
+            let full: Vec<u32> = cpu
+                .gfx
+                .iter()
+                .map(|pixel| display::color(*pixel, &options.palette, false))
+                .collect();
+            assert_eq!(screen.buffer, full);
+        }
+    }
+
+    // Lit pixels in column 0 after a low resolution D000 from 0x300, which
+    // holds 16 rows of 0x80
+    fn dxy0_rows(args: &[&str]) -> usize {
+        // A3 00, D0 00
+        let mut cpu =
+            CPU::from_rom(&[0xA3, 0x00, 0xD0, 0x00], vec![0; 64 * 32], &config(args)).unwrap();
+        for addr in 0x300..0x310 {
+            cpu.memory.write(addr, 0x80).unwrap();
+        }
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        (0..32).filter(|y| cpu.gfx[y * 64] == 1).count()
+    }
+
+    #[test]
+    fn lores_dxy0_draws_16_rows_only_with_its_quirk() {
+        assert_eq!(dxy0_rows(&["--quirk", "lores-dxy0-tall"]), 16);
+        assert_eq!(dxy0_rows(&["--no-quirk", "lores-dxy0-tall"]), 0);
+    }
+
+    #[test]
+    fn delay_wait_loop_counts_as_idle() {
+        // Sets the delay timer to 10, loops at 204-208 until it runs out,
+        // then halts
+        let mut cpu = cpu(&[
+            0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x12, 0x0A,
+        ]);
+        run_headless(&mut cpu, 1000).unwrap();
+
+        // Every pass after the first is idle, the first isn't known to be
+        // a loop until its jump back
+        assert_eq!((cpu.idle_cycles, cpu.cycles), (8, 14));
+    }
+
+    // I after FX1E adds 2 to I = 0xFFF
+    fn i_after_add(args: &[&str]) -> u16 {
+        // AF FF, 60 02, F0 1E
+        let rom = [0xAF, 0xFF, 0x60, 0x02, 0xF0, 0x1E];
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config(args)).unwrap();
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        cpu.I
+    }
+
+    #[test]
+    fn i_masks_to_the_variant_width() {
+        assert_eq!(i_after_add(&[]), 0x001);
+        assert_eq!(i_after_add(&["--compat", "cosmac"]), 0x001);
+        assert_eq!(i_after_add(&["--compat", "xochip"]), 0x1001);
+    }
+
+    #[test]
+    fn replay_reproduces_its_recorded_hash() {
+        // Waits for a key, draws its glyph then halts
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0x61, 0x00, 0xD1, 0x15, 0x12, 0x08];
+        let path = std::env::temp_dir().join("rusty-chip8-replay-verify.txt");
+        let script = path.to_str().unwrap();
+
+        // Press 7 for two frames, then 1 long after the ROM has halted
+        let mut recorder = Recorder::create(script).unwrap();
+        let mut keys = [false; 16];
+        for frame in 0..12 {
+            keys[0x7] = (2..4).contains(&frame);
+            keys[0x1] = (9..10).contains(&frame);
+            recorder.record(frame, &keys).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        // Hash at the end of the recorded session
+        let mut session = cpu(&rom);
+        let mut input = ScriptedInput::parse(&std::fs::read_to_string(script).unwrap()).unwrap();
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        super::run_headless(&mut session, &mut input, 1000, budget, |_, _| false).unwrap();
+        assert!(session.halted);
+        let hash = display::checksum(&session.pack_display());
+
+        let verify = |expected: u64| {
+            let args = [
+                "--replay",
+                script,
+                "--replay-verify",
+                &format!("{:X}", expected),
+            ];
+            let options = Options::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+            let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &options.config).unwrap();
+            run(
+                &mut cpu,
+                &options,
+                &mut Playlist::new(Vec::new()),
+                &Keymap::default(),
+            )
+        };
+        let verified = verify(hash);
+        let mismatched = verify(hash ^ 1);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(verified, Ok(()));
+        assert_eq!(
+            mismatched,
+            Err(EmulateError::ReplayMismatch(hash ^ 1, hash))
+        );
+    }
+
+    #[test]
+    fn fx33_near_the_top_of_memory_fails_cleanly() {
+        // AF FE, 60 FF, F0 33
+        let mut cpu = cpu(&[0xAF, 0xFE, 0x60, 0xFF, 0xF0, 0x33]);
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.I, 4094);
+
+        assert_eq!(cpu.emulate_cycle(), Err(EmulateError::OutOfBounds(4096)));
+        // Nothing was written
+        assert_eq!(cpu.memory.read(0xFFE), Ok(0));
+        assert_eq!(cpu.memory.read(0xFFF), Ok(0));
+    }
+
+    #[test]
+    fn timers_follow_a_mock_clock() {
+        let mut cpu = cpu(&[]);
+        cpu.delay_timer = 30;
+        let mut clock = MockClock::default();
+        let mut last = clock.now();
+
+        // A quarter of a second in uneven steps
+        for step in [FRAME * 2, FRAME / 2, FRAME * 10, FRAME / 2, FRAME * 2].iter() {
+            clock.advance(*step);
+            cpu.tick_timers(clock.elapsed(last));
+            last = clock.now();
+        }
+        assert_eq!(clock.now(), FRAME * 15);
+        assert_eq!(cpu.delay_timer, 15);
+    }
+
+    // CPU that has run FX55 storing V0-V1 = 0xAA, 0xBB to 0x1FF under
+    // --protect-interpreter
+    fn after_protected_store(args: &[&str]) -> (CPU, Result<(), EmulateError>) {
+        // A1 FF, 60 AA, 61 BB, F1 55
+        let rom = [0xA1, 0xFF, 0x60, 0xAA, 0x61, 0xBB, 0xF1, 0x55];
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config(args)).unwrap();
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        let result = cpu.emulate_cycle();
+        (cpu, result)
+    }
+
+    #[test]
+    fn protected_interpreter_refuses_fx55() {
+        let (cpu, result) = after_protected_store(&["--protect-interpreter", "error"]);
+        assert_eq!(result, Err(EmulateError::ProtectedWrite(0x1FF)));
+        assert_eq!(cpu.memory.read(0x1FF), Ok(0));
+    }
+
+    #[test]
+    fn protected_interpreter_can_ignore_fx55() {
+        let (cpu, result) = after_protected_store(&["--protect-interpreter", "ignore"]);
+        assert_eq!(result, Ok(()));
+        // Only the write past the interpreter lands
+        assert_eq!(cpu.memory.read(0x1FF), Ok(0));
+        assert_eq!(cpu.memory.read(0x200), Ok(0xBB));
+    }
+
+    #[test]
+    fn unprotected_fx55_writes_the_interpreter() {
+        let (cpu, result) = after_protected_store(&[]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(cpu.memory.read(0x1FF), Ok(0xAA));
+    }
+
+    // Lit pixels in row 0 and VF after drawing the 0 glyph twice at (0, 0)
+    fn after_drawing_twice(args: &[&str]) -> (usize, u8) {
+        // F0 29, D0 05, D0 05
+        let rom = [0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05];
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &config(args)).unwrap();
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        (cpu.gfx[..64].iter().sum::<u32>() as usize, cpu.V[0xF])
+    }
+
+    #[test]
+    fn xor_draws_erase_and_collide() {
+        assert_eq!(after_drawing_twice(&[]), (0, 1));
+        assert_eq!(after_drawing_twice(&["--draw-mode", "xor"]), (0, 1));
+    }
+
+    #[test]
+    fn or_draws_persist_without_collision() {
+        assert_eq!(after_drawing_twice(&["--draw-mode", "or"]), (4, 0));
+    }
+
+    #[test]
+    fn long_load_sets_i_and_steps_over_four_bytes() {
+        // F0 00 12 34, then 60 01
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x60, 0x01];
+        let mut cpu =
+            CPU::from_rom(&rom, vec![0; 64 * 32], &config(&["--compat", "xochip"])).unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!((cpu.I, cpu.pc), (0x1234, 0x204));
+
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0], 1);
+    }
+
+    #[test]
+    fn skip_steps_over_a_whole_long_load() {
+        // 30 00, F0 00 12 34, 60 01
+        let mut cpu = cpu(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01]);
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn hash_never_reached_is_an_error_not_an_exit() {
+        // Halts straight away with a blank display
+        let rom = [0x12, 0x00];
+        let args = ["--until-hash", "AB"];
+        let options = Options::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut cpu = CPU::from_rom(&rom, vec![0; 64 * 32], &options.config).unwrap();
+
+        assert_eq!(
+            run(
+                &mut cpu,
+                &options,
+                &mut Playlist::new(Vec::new()),
+                &Keymap::default(),
+            ),
+            Err(EmulateError::HashNotReached(0xAB))
+        );
+    }
+
+    #[test]
+    fn shift_flag_comes_from_the_shifted_register() {
+        let run = |rom: &[u8]| {
+            let mut cpu = cpu(rom);
+            for _ in 0..3 {
+                cpu.emulate_cycle().unwrap();
+            }
+            (cpu.V[0x0], cpu.V[0xF])
+        };
+
+        // 60 01, 61 80, 80 16: VY's low bit is 0 though VX's was 1
+        assert_eq!(run(&[0x60, 0x01, 0x61, 0x80, 0x80, 0x16]), (0x40, 0));
+        // 60 80, 61 01, 80 1E: VY's high bit is 0 though VX's was 1
+        assert_eq!(run(&[0x60, 0x80, 0x61, 0x01, 0x80, 0x1E]), (0x02, 0));
+    }
+
+    #[test]
+    fn shift_into_vf_keeps_the_flag() {
+        // 61 03, 8F 16 then 61 81, 8F 1E
+        let mut cpu = cpu(&[0x61, 0x03, 0x8F, 0x16, 0x61, 0x81, 0x8F, 0x1E]);
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0xF], 1);
+
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.V[0xF], 1);
+    }
 }
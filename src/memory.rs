@@ -0,0 +1,104 @@
+use crate::error::EmulateError;
+use rand::Rng;
+
+// All opcodes access memory through the bus rather than indexing RAM
+// directly, so variants with a larger address space (or memory mapped
+// I/O) only need to provide their own implementation.
+pub trait MemoryBus {
+    fn read(&self, addr: usize) -> Result<u8, EmulateError>;
+    fn write(&mut self, addr: usize, val: u8) -> Result<(), EmulateError>;
+
+    // Number of addressable bytes
+    fn size(&self) -> usize;
+}
+
+// Pattern left in memory that the ROM and fontset don't overwrite
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MemFill {
+    #[default]
+    Zero,
+    Ones,
+    Random,
+}
+
+// Chip 8 has 4K memory
+pub const CLASSIC_SIZE: usize = 0x1000;
+
+// XO-CHIP extends memory to 64K
+pub const XOCHIP_SIZE: usize = 0x10000;
+
+// Address ROMs are loaded at
+pub const ROM_START: usize = 0x200;
+
+pub struct RamBus {
+    ram: Vec<u8>,
+}
+
+impl RamBus {
+    pub fn new<R: Rng>(size: usize, fill: MemFill, rng: &mut R) -> RamBus {
+        let mut ram = vec![0x00; size];
+        match fill {
+            MemFill::Zero => {}
+            MemFill::Ones => ram = vec![0xFF; size],
+            MemFill::Random => rng.fill(&mut ram[..]),
+        }
+
+        RamBus { ram }
+    }
+}
+
+impl MemoryBus for RamBus {
+    fn read(&self, addr: usize) -> Result<u8, EmulateError> {
+        match self.ram.get(addr) {
+            Some(val) => Ok(*val),
+            None => Err(EmulateError::OutOfBounds(addr)),
+        }
+    }
+
+    fn write(&mut self, addr: usize, val: u8) -> Result<(), EmulateError> {
+        match self.ram.get_mut(addr) {
+            Some(loc) => {
+                *loc = val;
+                Ok(())
+            }
+            None => Err(EmulateError::OutOfBounds(addr)),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.ram.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn ram(fill: MemFill) -> RamBus {
+        RamBus::new(CLASSIC_SIZE, fill, &mut StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut ram = ram(MemFill::Zero);
+        ram.write(0x000, 0x12).unwrap();
+        ram.write(0xFFF, 0xAB).unwrap();
+
+        assert_eq!(ram.read(0x000), Ok(0x12));
+        assert_eq!(ram.read(0xFFF), Ok(0xAB));
+        assert_eq!(ram.read(0x800), Ok(0x00));
+        assert_eq!(ram.size(), CLASSIC_SIZE);
+    }
+
+    #[test]
+    fn access_past_the_end_is_out_of_bounds() {
+        let mut ram = ram(MemFill::Zero);
+        assert_eq!(ram.read(0x1000), Err(EmulateError::OutOfBounds(0x1000)));
+        assert_eq!(
+            ram.write(0x1000, 0xFF),
+            Err(EmulateError::OutOfBounds(0x1000))
+        );
+    }
+}
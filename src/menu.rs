@@ -0,0 +1,174 @@
+use crate::keymap::Keymap;
+use crate::overlay;
+use minifb::Key;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Size of the picker, the high resolution display
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+
+// Lines of text that fit in the picker
+const ROWS: usize = HEIGHT / overlay::LINE_HEIGHT;
+
+// Input the ROM picker responds to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuEvent {
+    Up,
+    Down,
+    Select,
+}
+
+// Arrow keys and Enter, or 2, 8 and 5 on the hex keypad, which sit in a
+// column in its middle
+pub fn event(key: Key, keymap: &Keymap) -> Option<MenuEvent> {
+    match key {
+        Key::Up => Some(MenuEvent::Up),
+        Key::Down => Some(MenuEvent::Down),
+        Key::Enter => Some(MenuEvent::Select),
+        _ => match keymap.lookup(key)? {
+            0x2 => Some(MenuEvent::Up),
+            0x8 => Some(MenuEvent::Down),
+            0x5 => Some(MenuEvent::Select),
+            _ => None,
+        },
+    }
+}
+
+// List of ROMs to choose from, scrolled so the selected one is visible
+pub struct Menu {
+    items: Vec<String>,
+    selected: usize,
+
+    // First item shown
+    top: usize,
+}
+
+impl Menu {
+    pub fn new(items: Vec<String>) -> Menu {
+        Menu {
+            items,
+            selected: 0,
+            top: 0,
+        }
+    }
+
+    // Moves the selection, wrapping at either end. Gives the chosen item
+    // on Select.
+    pub fn handle(&mut self, event: MenuEvent) -> Option<&str> {
+        let len = self.items.len();
+        if len == 0 {
+            return None;
+        }
+
+        match event {
+            MenuEvent::Up => self.selected = (self.selected + len - 1) % len,
+            MenuEvent::Down => self.selected = (self.selected + 1) % len,
+            MenuEvent::Select => return Some(&self.items[self.selected]),
+        }
+
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + ROWS {
+            self.top = self.selected + 1 - ROWS;
+        }
+
+        None
+    }
+
+    // Lines currently shown, the selected one marked with >
+    pub fn lines(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .enumerate()
+            .skip(self.top)
+            .take(ROWS)
+            .map(|(index, path)| {
+                let name = Path::new(path)
+                    .file_name()
+                    .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+                let marker = if index == self.selected { '>' } else { ' ' };
+                format!("{}{}", marker, name)
+            })
+            .collect()
+    }
+
+    // Draws the menu over the whole of a WIDTH by HEIGHT buffer
+    pub fn draw(&self, buffer: &mut [u32]) {
+        overlay::fill_rect(buffer, WIDTH, 0, 0, WIDTH, HEIGHT, 0);
+
+        for (row, line) in self.lines().iter().enumerate() {
+            overlay::draw_text(buffer, WIDTH, 0, row * overlay::LINE_HEIGHT, line);
+        }
+    }
+}
+
+// Files in dir, sorted by name
+pub fn list_roms(dir: &str) -> io::Result<Vec<String>> {
+    let mut roms = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // Settings files aren't ROMs
+        if path.is_file() && path.extension().is_none_or(|ext| ext != "toml") {
+            roms.push(path.to_string_lossy().into_owned());
+        }
+    }
+    roms.sort();
+
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu(len: usize) -> Menu {
+        Menu::new((0..len).map(|i| format!("roms/{:02}", i)).collect())
+    }
+
+    #[test]
+    fn selection_wraps_at_either_end() {
+        let mut menu = menu(3);
+
+        menu.handle(MenuEvent::Up);
+        assert_eq!(menu.handle(MenuEvent::Select), Some("roms/02"));
+
+        menu.handle(MenuEvent::Down);
+        assert_eq!(menu.handle(MenuEvent::Select), Some("roms/00"));
+    }
+
+    #[test]
+    fn scrolls_to_keep_the_selection_shown() {
+        let mut menu = menu(ROWS + 2);
+        for _ in 0..ROWS {
+            menu.handle(MenuEvent::Down);
+        }
+
+        let lines = menu.lines();
+        assert_eq!(lines.len(), ROWS);
+        assert_eq!(lines[0], " 01");
+        assert_eq!(lines[ROWS - 1], format!(">{:02}", ROWS));
+
+        // Wrapping to the first item scrolls back to the top
+        menu.handle(MenuEvent::Down);
+        menu.handle(MenuEvent::Down);
+        assert_eq!(menu.lines()[0], ">00");
+    }
+
+    #[test]
+    fn empty_menu_selects_nothing() {
+        assert_eq!(menu(0).handle(MenuEvent::Select), None);
+    }
+
+    #[test]
+    fn keypad_column_drives_the_menu() {
+        let keymap = Keymap::default();
+        assert_eq!(event(Key::Up, &keymap), Some(MenuEvent::Up));
+        assert_eq!(event(Key::Key2, &keymap), Some(MenuEvent::Up));
+        assert_eq!(event(Key::S, &keymap), Some(MenuEvent::Down));
+        assert_eq!(event(Key::W, &keymap), Some(MenuEvent::Select));
+        assert_eq!(event(Key::Key1, &keymap), None);
+    }
+}
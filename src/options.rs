@@ -0,0 +1,1115 @@
+use crate::display::{self, DrawMode};
+use crate::error::{ProtectedWritePolicy, UnknownOpcodePolicy};
+use crate::export::Lang;
+use crate::keymap::{self, Keymap};
+use crate::memory::{self, MemFill};
+use crate::playlist;
+use crate::quirk_test;
+use crate::quirks::{self, Quirks};
+use crate::theme;
+use crate::timing::{self, FrameBudget};
+use crate::trace::TraceFormat;
+use crate::Config;
+use log::LevelFilter;
+use minifb::Key;
+use std::str::FromStr;
+
+// Settings parsed from the command line
+pub struct Options {
+    // ROMs to cycle through, the first is loaded on startup
+    pub roms: Vec<String>,
+    pub config: Config,
+
+    // Print the keypad mapping and exit
+    pub show_keys: bool,
+
+    // Print the files in this zip archive and exit
+    #[cfg(feature = "zip")]
+    pub list_archive: Option<String>,
+
+    // Physical keys that drive the hex keypad
+    pub keymap: Keymap,
+
+    // Directory to pick a ROM from in a menu, when none was given
+    pub pick_from: Option<String>,
+
+    // Key that closes the window
+    pub quit_key: Key,
+
+    // Run the built-in opcode checks and exit
+    pub self_test: bool,
+
+    // Run this many random opcodes from random states, checking none panic
+    pub fuzz: Option<u64>,
+
+    // Time this many DXYN draws and exit
+    pub bench_draw: Option<u64>,
+
+    // Print a labelled listing of the ROM and exit
+    pub dump_disasm: bool,
+
+    // Addresses START..END to list, rather than the whole ROM
+    pub disasm_range: Option<(usize, usize)>,
+
+    // Print the ROM as a byte array in this language and exit
+    pub export_array: Option<Lang>,
+
+    // Print the font glyphs as loaded into memory and exit
+    pub dump_fontset: bool,
+
+    // Instructions executed per 60 Hz frame
+    pub cycles_per_frame: u32,
+
+    // Run this many instructions per second instead of a number per frame
+    pub slow: Option<u32>,
+
+    // Most frames drawn to the window per second
+    pub draw_rate: Option<u32>,
+
+    // Budget each frame in VIP machine cycles using per-opcode costs
+    pub cycle_accurate: bool,
+
+    // Pause before each instruction and take debugger commands from stdin
+    pub step: bool,
+
+    // Open the window paused, before the first instruction has run
+    pub start_paused: bool,
+
+    // Pause while the window doesn't have focus
+    pub pause_on_blur: bool,
+
+    // Run this many instructions before dropping into step mode
+    pub run_to: Option<u64>,
+
+    // Run without a window until the ROM halts
+    pub headless: bool,
+
+    // Instructions a headless run may execute before it's abandoned
+    pub max_cycles: u64,
+
+    // Print which opcodes were executed after a headless run
+    pub opcode_coverage: bool,
+
+    // Print this many of the most executed addresses after a headless run
+    pub profile_hotspots: Option<usize>,
+
+    // Print how many instructions were spent in busy-wait loops on exit
+    pub count_idle: bool,
+
+    // Print the final display as text after a headless run
+    pub dump_gfx: bool,
+
+    // Print a hash of the display after each frame of a headless run
+    pub checksum: bool,
+
+    // Stop a headless run once the display hashes to this
+    pub until_hash: Option<u64>,
+
+    // Hash the display must have once the replay script runs out
+    pub replay_verify: Option<u64>,
+
+    // Run the ROM as the Timendus quirks test, printing which quirks pass
+    pub quirk_test: bool,
+
+    // File to write the final CPU state to as JSON, however the run ends
+    pub dump_state: Option<String>,
+
+    // State file written by --dump-state-on-exit to resume from at launch
+    pub load_state: Option<String>,
+
+    // File to write diagnostics to instead of stderr
+    pub log_file: Option<String>,
+
+    // Most detailed diagnostics written, warnings and errors by default
+    pub log_level: LevelFilter,
+
+    // File to write a line of CPU state to after every instruction
+    pub trace: Option<String>,
+
+    // Line layout used by --trace and --diff-trace
+    pub trace_format: TraceFormat,
+
+    // Reference trace to compare each instruction's state against
+    pub diff_trace: Option<String>,
+
+    // Quirks for a second CPU run in lockstep with the first, reporting
+    // where the two first differ
+    pub compare_quirks: Option<Quirks>,
+
+    // Quirks picked by name, or all of them once a preset is chosen. Strict
+    // mode refuses opcodes that depend on any other.
+    pub chosen_quirks: Vec<String>,
+
+    // Frames for an unlit pixel to fade out, 0 turns pixels off instantly
+    pub fade: u32,
+
+    // Output color for each pixel value
+    pub palette: [u32; 4],
+
+    // Register to show in the corner of the display
+    pub watch_reg: Option<usize>,
+
+    // Start with the on and off colors swapped
+    pub invert: bool,
+
+    // Outline the display while the sound timer is running
+    pub visual_beep: bool,
+
+    // Draw only every Nth frame, everything else still runs each frame
+    pub frameskip: u32,
+
+    // Window size as a multiple of the display resolution
+    pub scale: u32,
+
+    // Script of key events to play back instead of reading the keyboard
+    pub replay: Option<String>,
+
+    // File to write key presses to, in the format read by --replay
+    pub record_input: Option<String>,
+
+    // Start of the window title, followed by the ROM name and stats
+    pub title: String,
+
+    // Also show instructions executed per frame in the title
+    pub verbose: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            roms: Vec::new(),
+            config: Config::default(),
+            show_keys: false,
+            #[cfg(feature = "zip")]
+            list_archive: None,
+            keymap: Keymap::default(),
+            pick_from: None,
+            quit_key: Key::Escape,
+            self_test: false,
+            fuzz: None,
+            bench_draw: None,
+            dump_disasm: false,
+            disasm_range: None,
+            dump_fontset: false,
+            export_array: None,
+            cycles_per_frame: 1,
+            slow: None,
+            draw_rate: None,
+            cycle_accurate: false,
+            step: false,
+            start_paused: false,
+            pause_on_blur: false,
+            run_to: None,
+            headless: false,
+            max_cycles: 50_000_000,
+            opcode_coverage: false,
+            profile_hotspots: None,
+            count_idle: false,
+            dump_gfx: false,
+            checksum: false,
+            until_hash: None,
+            replay_verify: None,
+            quirk_test: false,
+            dump_state: None,
+            load_state: None,
+            log_file: None,
+            log_level: LevelFilter::Warn,
+            trace: None,
+            trace_format: TraceFormat::Default,
+            diff_trace: None,
+            compare_quirks: None,
+            chosen_quirks: Vec::new(),
+            fade: 0,
+            palette: display::DEFAULT_PALETTE,
+            watch_reg: None,
+            invert: false,
+            visual_beep: false,
+            frameskip: 1,
+            scale: 16,
+            replay: None,
+            record_input: None,
+            title: String::from("Chip-8"),
+            verbose: false,
+        }
+    }
+}
+
+impl Options {
+    // Parses the command line on top of the settings file kept next to the
+    // first ROM, if there is one, so flags given explicitly win. A preset
+    // on the command line replaces every quirk the settings file chose.
+    pub fn load(args: Vec<String>) -> Result<Options, String> {
+        let options = Options::parse(args.clone().into_iter())?;
+
+        let path = match options.roms.first() {
+            Some(rom) => sidecar_path(rom),
+            None => return Ok(options),
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return Ok(options),
+        };
+        let sidecar = sidecar_args(&text)
+            .and_then(|flags| Options::parse(flags.into_iter()))
+            .map_err(|e| format!("{}: {}", path, e))?;
+
+        Options::parse_over(sidecar, args.into_iter())
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+        Options::parse_over(Options::default(), args)
+    }
+
+    // Parses flags on top of settings already chosen, each flag replacing
+    // what it sets
+    fn parse_over<I: Iterator<Item = String>>(
+        mut options: Options,
+        mut args: I,
+    ) -> Result<Options, String> {
+        // Individual quirks, applied on top of any preset however the
+        // flags were ordered
+        let mut overrides: Vec<(String, bool)> = Vec::new();
+        let mut strict = options.config.strict;
+
+        // Language for --export-c-array
+        let mut lang = Lang::default();
+        let mut export_array = false;
+
+        // Preset, or quirk to flip, for --compare-backends
+        let mut compare = None;
+
+        // Applied over the memory size a preset chose, whichever came first
+        let mut max_rom_size = None;
+
+        // Directory relative ROM paths are found in, the working directory
+        // when not given
+        let mut rom_dir = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--mem-fill" => {
+                    options.config.mem_fill = match value(&mut args, &arg)?.as_str() {
+                        "zero" => MemFill::Zero,
+                        "ones" => MemFill::Ones,
+                        "random" => MemFill::Random,
+                        other => return Err(format!("Unknown memory fill: {}", other)),
+                    }
+                }
+                "--keys" => options.show_keys = true,
+                #[cfg(feature = "zip")]
+                "--list" => options.list_archive = Some(value(&mut args, &arg)?),
+                "--quit-key" => {
+                    let name = value(&mut args, &arg)?;
+                    options.quit_key =
+                        keymap::parse_key(&name).ok_or_else(|| format!("Unknown key: {}", name))?;
+                }
+                "--keymap" => {
+                    let name = value(&mut args, &arg)?;
+                    options.keymap = Keymap::layout(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown keymap: {}, expected one of {}",
+                            name,
+                            keymap::LAYOUTS.join(", ")
+                        )
+                    })?;
+                }
+                "--rom-dir" => rom_dir = Some(value(&mut args, &arg)?),
+                "--self-test" => options.self_test = true,
+                "--fuzz" => options.fuzz = Some(number(&mut args, &arg)?),
+                "--bench-draw" => options.bench_draw = Some(number(&mut args, &arg)?),
+                "--dump-disasm-with-addresses" => options.dump_disasm = true,
+                "--disasm-range" => {
+                    options.disasm_range = Some(parse_range(&value(&mut args, &arg)?)?);
+                    options.dump_disasm = true;
+                }
+                "--dump-fontset" => options.dump_fontset = true,
+                "--export-c-array" => export_array = true,
+                "--lang" => {
+                    lang = match value(&mut args, &arg)?.as_str() {
+                        "rust" => Lang::Rust,
+                        "c" => Lang::C,
+                        other => {
+                            return Err(format!("Unknown language: {}, expected rust or c", other))
+                        }
+                    }
+                }
+                "--cycles-per-frame" => {
+                    options.cycles_per_frame = match number(&mut args, &arg)? {
+                        0 => return Err(String::from("Cycles per frame must be at least 1")),
+                        cycles => cycles,
+                    }
+                }
+                "--cycle-accurate" => options.cycle_accurate = true,
+                "--slow" => {
+                    options.slow = match number(&mut args, &arg)? {
+                        0 => {
+                            return Err(String::from("Instructions per second must be at least 1"))
+                        }
+                        rate => Some(rate),
+                    }
+                }
+                "--step" => options.step = true,
+                "--start-paused" => options.start_paused = true,
+                "--pause-on-blur" => options.pause_on_blur = true,
+                "--run-to" => {
+                    options.run_to = Some(number(&mut args, &arg)?);
+                    options.step = true;
+                }
+                "--headless" | "--no-window" => options.headless = true,
+                "--max-cycles" => options.max_cycles = number(&mut args, &arg)?,
+                "--opcode-coverage" => {
+                    options.headless = true;
+                    options.opcode_coverage = true;
+                }
+                "--profile-hotspots" => {
+                    options.headless = true;
+                    options.profile_hotspots = Some(number(&mut args, &arg)?);
+                }
+                "--count-idle" => options.count_idle = true,
+                "--dump-gfx" => {
+                    options.headless = true;
+                    options.dump_gfx = true;
+                }
+                "--checksum" => {
+                    options.headless = true;
+                    options.checksum = true;
+                }
+                "--until-hash" => {
+                    let hash = value(&mut args, &arg)?;
+                    options.until_hash = Some(parse_hash(&hash)?);
+                    options.headless = true;
+                    options.checksum = true;
+                }
+                "--replay-verify" => {
+                    let hash = value(&mut args, &arg)?;
+                    options.replay_verify = Some(parse_hash(&hash)?);
+                    options.headless = true;
+                }
+                "--poke" => {
+                    let poke = value(&mut args, &arg)?;
+                    options.config.pokes.push(parse_poke(&poke)?);
+                }
+                "--poke-force" => options.config.force_poke = true,
+                "--no-fontset" => options.config.no_fontset = true,
+                "--hex" => options.config.hex_rom = true,
+                "--rand-regs" => options.config.rand_regs = true,
+                "--draw-mode" => {
+                    options.config.draw_mode = match value(&mut args, &arg)?.as_str() {
+                        "xor" => DrawMode::Xor,
+                        "or" => DrawMode::Or,
+                        "and" => DrawMode::And,
+                        "subtract" => DrawMode::Subtract,
+                        other => {
+                            return Err(format!(
+                                "Unknown draw mode: {}, expected xor, or, and or subtract",
+                                other
+                            ))
+                        }
+                    };
+                }
+                "--protect-interpreter" => {
+                    options.config.protect_interpreter = match value(&mut args, &arg)?.as_str() {
+                        "error" => ProtectedWritePolicy::Error,
+                        "ignore" => ProtectedWritePolicy::Ignore,
+                        other => {
+                            return Err(format!(
+                                "Unknown protected write policy: {}, expected error or ignore",
+                                other
+                            ))
+                        }
+                    };
+                }
+                "--on-unknown" => {
+                    options.config.on_unknown = match value(&mut args, &arg)?.as_str() {
+                        "error" => UnknownOpcodePolicy::Error,
+                        "halt" => UnknownOpcodePolicy::Halt,
+                        "skip" => UnknownOpcodePolicy::Skip,
+                        other => {
+                            return Err(format!(
+                                "Unknown opcode policy: {}, expected error, halt or skip",
+                                other
+                            ))
+                        }
+                    }
+                }
+                "--dump-state-on-exit" => options.dump_state = Some(value(&mut args, &arg)?),
+                "--load-state" | "--autostart-with-state" => {
+                    options.load_state = Some(value(&mut args, &arg)?)
+                }
+                "--log-file" => options.log_file = Some(value(&mut args, &arg)?),
+                "--log-level" => {
+                    let level = value(&mut args, &arg)?;
+                    options.log_level = level.parse().map_err(|_| {
+                        format!(
+                            "Unknown log level: {}, expected off, error, warn, info, debug or trace",
+                            level
+                        )
+                    })?;
+                }
+                "--trace" => options.trace = Some(value(&mut args, &arg)?),
+                "--trace-format" => {
+                    options.trace_format = match value(&mut args, &arg)?.as_str() {
+                        "default" => TraceFormat::Default,
+                        "octo" => TraceFormat::Octo,
+                        "json" => TraceFormat::Json,
+                        other => return Err(format!("Unknown trace format: {}", other)),
+                    }
+                }
+                "--diff-trace" => options.diff_trace = Some(value(&mut args, &arg)?),
+                "--fade" => options.fade = number(&mut args, &arg)?,
+                "--fg" => options.palette[1] = parse_color(&value(&mut args, &arg)?)?,
+                "--bg" => options.palette[0] = parse_color(&value(&mut args, &arg)?)?,
+                "--watch-reg" => {
+                    options.watch_reg = Some(parse_register(&value(&mut args, &arg)?)?)
+                }
+                "--theme" => {
+                    let (off, on) = theme::load(&value(&mut args, &arg)?)?;
+                    options.palette[0] = off;
+                    options.palette[1] = on;
+                }
+                "--invert" => options.invert = true,
+                "--frameskip" => options.frameskip = number(&mut args, &arg)?,
+                "--scale" => options.scale = number(&mut args, &arg)?,
+                "--palette" => options.palette = parse_palette(&value(&mut args, &arg)?)?,
+                "--seed" => options.config.seed = Some(number(&mut args, &arg)?),
+                "--compat" => {
+                    let name = value(&mut args, &arg)?;
+                    options.config.quirks = Quirks::preset(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown compat preset: {}, expected one of {}",
+                            name,
+                            quirks::PRESETS.join(", ")
+                        )
+                    })?;
+                    options.chosen_quirks =
+                        quirks::NAMES.iter().map(|name| name.to_string()).collect();
+
+                    // XO-CHIP ROMs may use all of its larger memory, which
+                    // takes a 16 bit I to reach
+                    (options.config.memory_size, options.config.i_mask) = match name.as_str() {
+                        "xochip" => (memory::XOCHIP_SIZE, 0xFFFF),
+                        _ => (memory::CLASSIC_SIZE, 0x0FFF),
+                    };
+                }
+                "--authentic" => {
+                    // The COSMAC VIP as it looked: its quirks and timing, with
+                    // no more draws than its 60 Hz display showed
+                    options.config.quirks = Quirks::preset("cosmac").unwrap();
+                    options.chosen_quirks =
+                        quirks::NAMES.iter().map(|name| name.to_string()).collect();
+                    options.config.memory_size = memory::CLASSIC_SIZE;
+                    options.config.i_mask = 0x0FFF;
+                    options.cycle_accurate = true;
+                    options.frameskip = 1;
+                    options.draw_rate = Some(60);
+                }
+                "--max-rom-size" => {
+                    // Memory grows to fit, up to the 64K a 16 bit address reaches
+                    let max = memory::XOCHIP_SIZE - memory::ROM_START;
+                    let size: usize = number(&mut args, &arg)?;
+                    if size == 0 || size > max {
+                        return Err(format!("ROM size must be 1-{}: {}", max, size));
+                    }
+                    max_rom_size = Some(size);
+                }
+                "--strict" => strict = true,
+                "--compare-backends" => compare = Some(value(&mut args, &arg)?),
+                "--stack-depth" => {
+                    // The stack pointer is a single byte
+                    options.config.stack_depth = match number(&mut args, &arg)? {
+                        depth @ 1..=255 => depth,
+                        depth => return Err(format!("Stack depth must be 1-255: {}", depth)),
+                    }
+                }
+                "--quirk-test" => {
+                    options.roms.insert(0, value(&mut args, &arg)?);
+                    options.quirk_test = true;
+
+                    // Pick the platform up front rather than answering the
+                    // menu. Pokes given on the command line still apply
+                    // afterwards.
+                    let platform = (quirk_test::PLATFORM_ADDR, quirk_test::PLATFORM_CHIP8);
+                    options.config.pokes.insert(0, platform);
+                }
+                "--quirk" => overrides.push((value(&mut args, &arg)?, true)),
+                "--no-quirk" => overrides.push((value(&mut args, &arg)?, false)),
+                "--replay" => options.replay = Some(value(&mut args, &arg)?),
+                "--record-input" => options.record_input = Some(value(&mut args, &arg)?),
+                "--title" => options.title = value(&mut args, &arg)?,
+                "--verbose" => options.verbose = true,
+                "--visual-beep" => options.visual_beep = true,
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                _ => options.roms.push(arg),
+            }
+        }
+
+        for (name, on) in overrides {
+            options.config.quirks.set(&name, on)?;
+            options.chosen_quirks.push(name);
+        }
+
+        // A preset settles every quirk, otherwise only those given one by
+        // one are known to be what the ROM wants
+        options.config.strict = strict;
+        if strict {
+            options.config.ambiguous_quirks = quirks::NAMES
+                .iter()
+                .filter(|name| !options.chosen_quirks.iter().any(|chosen| chosen == *name))
+                .cloned()
+                .collect();
+        }
+
+        if let Some(name) = compare {
+            let quirks = match Quirks::preset(&name) {
+                Some(preset) => preset,
+                None => {
+                    let mut flipped = options.config.quirks;
+                    let on = flipped
+                        .get(&name)
+                        .map_err(|_| format!("Expected a compat preset or quirk name: {}", name))?;
+                    flipped.set(&name, !on)?;
+                    flipped
+                }
+            };
+            options.compare_quirks = Some(quirks);
+
+            // Both CPUs need the same random numbers
+            if options.config.seed.is_none() {
+                options.config.seed = Some(0);
+            }
+        }
+
+        if options.replay_verify.is_some() && options.replay.is_none() {
+            return Err(String::from(
+                "--replay-verify needs a script given with --replay",
+            ));
+        }
+
+        // The quit key can't also be pressed for the ROM
+        if let Some(hex) = options.keymap.lookup(options.quit_key) {
+            return Err(format!(
+                "Quit key {} is already mapped to key {:X}",
+                keymap::key_name(options.quit_key),
+                hex
+            ));
+        }
+
+        if export_array {
+            options.export_array = Some(lang);
+        }
+
+        options.pick_from = if options.roms.is_empty() {
+            Some(rom_dir.clone().unwrap_or_else(|| String::from("c8games")))
+        } else {
+            None
+        };
+
+        if let Some(size) = max_rom_size {
+            options.config.memory_size = memory::ROM_START + size;
+
+            // Memory past 4K takes a 16 bit I to reach
+            if options.config.memory_size > memory::CLASSIC_SIZE {
+                options.config.i_mask = 0xFFFF;
+            }
+        }
+
+        if let Some(dir) = &rom_dir {
+            options.roms = options
+                .roms
+                .iter()
+                .map(|rom| playlist::resolve(dir, rom))
+                .collect();
+        }
+
+        // Replays only reproduce a run when the random numbers do too
+        if options.replay.is_some() && options.config.seed.is_none() {
+            options.config.seed = Some(0);
+        }
+
+        Ok(options)
+    }
+
+    // Whether the run ends in the emulator window, rather than printing
+    // something or running headless and exiting
+    pub fn opens_window(&self) -> bool {
+        !(self.headless
+            || self.diff_trace.is_some()
+            || self.dump_fontset
+            || self.compare_quirks.is_some()
+            || self.quirk_test
+            || self.dump_disasm
+            || self.export_array.is_some())
+    }
+
+    // Whether a mode without a window has to be given a ROM. Dumping the
+    // fontset reads it from memory, which is the same with no ROM loaded.
+    pub fn needs_rom(&self) -> bool {
+        !self.dump_fontset
+            || self.diff_trace.is_some()
+            || self.dump_disasm
+            || self.export_array.is_some()
+    }
+
+    pub fn frame_budget(&self) -> FrameBudget {
+        if self.cycle_accurate {
+            FrameBudget {
+                limit: timing::VIP_CYCLES_PER_FRAME,
+                cycle_accurate: true,
+            }
+        } else {
+            FrameBudget {
+                limit: self.cycles_per_frame,
+                cycle_accurate: false,
+            }
+        }
+    }
+}
+
+// Pulls the value following a flag
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+// Pulls the numeric value following a flag
+fn number<T: FromStr, I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<T, String> {
+    let value = value(args, flag)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number for {}: {}", flag, value))
+}
+
+// Parses ADDR=VAL, each either decimal or 0x prefixed hex
+fn parse_poke(poke: &str) -> Result<(usize, u8), String> {
+    let invalid = || format!("Invalid poke, expected ADDR=VAL: {}", poke);
+
+    let mut parts = poke.splitn(2, '=');
+    let addr = parts.next().and_then(parse_int).ok_or_else(invalid)?;
+    let val = parts.next().and_then(parse_int).ok_or_else(invalid)?;
+
+    if val > 0xFF {
+        return Err(invalid());
+    }
+
+    Ok((addr as usize, val as u8))
+}
+
+// Parses START:END, each either decimal or 0x prefixed hex. END is
+// exclusive.
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid range, expected START:END: {}", range);
+
+    let mut parts = range.splitn(2, ':');
+    let start = parts.next().and_then(parse_int).ok_or_else(invalid)?;
+    let end = parts.next().and_then(parse_int).ok_or_else(invalid)?;
+
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok((start as usize, end as usize))
+}
+
+// Register number as a single hex digit, optionally prefixed with V
+pub fn parse_register(reg: &str) -> Result<usize, String> {
+    let digit = reg.strip_prefix(|c| c == 'V' || c == 'v').unwrap_or(reg);
+
+    match usize::from_str_radix(digit, 16) {
+        Ok(index) if index < 16 => Ok(index),
+        _ => Err(format!("Invalid register, expected 0-F: {}", reg)),
+    }
+}
+
+// Settings file for a ROM, game.ch8.toml for game.ch8
+pub fn sidecar_path(rom: &str) -> String {
+    format!("{}.toml", rom)
+}
+
+// Settings a ROM's settings file may give: how the ROM runs and looks.
+// Anything that reads or writes other files has to be given on the command
+// line.
+const SIDECAR_KEYS: [&str; 10] = [
+    "compat",
+    "quirk",
+    "no-quirk",
+    "keymap",
+    "fg",
+    "bg",
+    "palette",
+    "hz",
+    "cycles-per-frame",
+    "cycle-accurate",
+];
+
+// Turns the `name = value` lines of a ROM's settings file into the flags
+// they stand for, `compat = "schip"` being --compat schip. true gives a
+// flag without a value and false leaves it out, while an array of strings
+// repeats the flag once for each. hz is instructions per second, given as
+// the nearest --cycles-per-frame. A # outside of quotes starts a comment.
+fn sidecar_args(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || format!("Invalid setting on line {}: {}", number + 1, line);
+
+        let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+        let (name, value) = (name.trim(), value.trim());
+        if !SIDECAR_KEYS.contains(&name) {
+            return Err(invalid());
+        }
+        let flag = format!("--{}", name);
+
+        match value {
+            "true" => args.push(flag),
+            "false" => {}
+            _ if name == "hz" => {
+                let hz: u32 = value.parse().map_err(|_| invalid())?;
+                if hz == 0 {
+                    return Err(invalid());
+                }
+                args.push(String::from("--cycles-per-frame"));
+                args.push(((hz + 30) / 60).max(1).to_string());
+            }
+            _ => {
+                let values = match value.strip_prefix('[') {
+                    Some(list) => list
+                        .strip_suffix(']')
+                        .ok_or_else(invalid)?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .collect(),
+                    None => vec![value],
+                };
+
+                for value in values {
+                    let value = value.trim_matches('"');
+                    args.push(flag.clone());
+                    args.push(value.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+// Line of a settings file up to any # that isn't inside a quoted string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Display hash as printed by --checksum, hex with an optional 0x prefix
+fn parse_hash(hash: &str) -> Result<u64, String> {
+    let digits = hash
+        .strip_prefix("0x")
+        .or_else(|| hash.strip_prefix("0X"))
+        .unwrap_or(hash);
+
+    u64::from_str_radix(digits, 16).map_err(|_| format!("Invalid hash: {}", hash))
+}
+
+// Decimal or 0x prefixed hex
+pub fn parse_int(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// Parses four comma separated hex RGB colors
+fn parse_palette(palette: &str) -> Result<[u32; 4], String> {
+    let colors: Vec<&str> = palette.split(',').collect();
+    if colors.len() != 4 {
+        return Err(format!("Palette needs exactly four colors: {}", palette));
+    }
+
+    let mut parsed = [0; 4];
+    for (i, color) in colors.iter().enumerate() {
+        parsed[i] = parse_color(color)?;
+    }
+
+    Ok(parsed)
+}
+
+// Hex RGB color, optionally prefixed with # or 0x
+fn parse_color(color: &str) -> Result<u32, String> {
+    let hex = color.trim();
+    let hex = hex
+        .strip_prefix('#')
+        .or_else(|| hex.strip_prefix("0x"))
+        .unwrap_or(hex);
+
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok(rgb),
+        _ => Err(format!("Invalid color: {}", color)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        Options::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn authentic_sets_the_cosmac_quirks_and_timing() {
+        let options = parse(&["--authentic"]);
+        assert_eq!(options.config.quirks, Quirks::preset("cosmac").unwrap());
+        assert!(options.config.quirks.display_wait);
+        assert_eq!(options.config.memory_size, memory::CLASSIC_SIZE);
+        assert_eq!(options.config.i_mask, 0x0FFF);
+        assert!(options.cycle_accurate);
+        assert_eq!(options.frameskip, 1);
+        assert_eq!(options.draw_rate, Some(60));
+    }
+
+    #[test]
+    fn max_rom_size_widens_i_whatever_the_flag_order() {
+        for args in [
+            ["--max-rom-size", "8192", "--compat", "cosmac"],
+            ["--compat", "cosmac", "--max-rom-size", "8192"],
+        ]
+        .iter()
+        {
+            let options = parse(args);
+            assert_eq!(options.config.memory_size, memory::ROM_START + 8192);
+            assert_eq!(options.config.i_mask, 0xFFFF);
+        }
+
+        // Memory that still fits in 4K keeps the preset's 12 bit I
+        let options = parse(&["--compat", "cosmac", "--max-rom-size", "1024"]);
+        assert_eq!(options.config.memory_size, memory::ROM_START + 1024);
+        assert_eq!(options.config.i_mask, 0x0FFF);
+    }
+
+    #[test]
+    fn no_rom_picks_from_the_rom_dir() {
+        let options = parse(&[]);
+        assert!(options.roms.is_empty());
+        assert_eq!(options.pick_from, Some(String::from("c8games")));
+
+        let options = parse(&["--rom-dir", "roms"]);
+        assert_eq!(options.pick_from, Some(String::from("roms")));
+
+        let options = parse(&["PONG"]);
+        assert_eq!(options.pick_from, None);
+    }
+
+    #[test]
+    fn only_the_window_run_opens_a_window() {
+        assert!(parse(&[]).opens_window());
+        assert!(parse(&["--trace", "out.txt"]).opens_window());
+        // Stops at the cycle then carries on in the window
+        assert!(parse(&["--run-to", "100"]).opens_window());
+
+        for flag in [
+            "--dump-fontset",
+            "--dump-disasm-with-addresses",
+            "--headless",
+        ]
+        .iter()
+        {
+            assert!(!parse(&[flag]).opens_window(), "{}", flag);
+        }
+        assert!(!parse(&["--quirk-test", "quirks.ch8"]).opens_window());
+        assert!(!parse(&["--diff-trace", "ref.txt"]).opens_window());
+    }
+
+    #[test]
+    fn only_the_fontset_dump_runs_without_a_rom() {
+        assert!(!parse(&["--dump-fontset"]).needs_rom());
+        assert!(parse(&["--dump-disasm-with-addresses"]).needs_rom());
+        assert!(parse(&["--headless"]).needs_rom());
+        assert!(parse(&["--run-to", "100"]).needs_rom());
+    }
+
+    #[test]
+    fn palette_maps_each_pixel_value_to_its_color() {
+        let options = parse(&["--palette", "#101010,0x202020,303030,404040"]);
+        assert_eq!(options.palette, [0x101010, 0x202020, 0x303030, 0x404040]);
+
+        for (pixel, color) in options.palette.iter().enumerate() {
+            assert_eq!(
+                display::color(pixel as u32, &options.palette, false),
+                *color
+            );
+        }
+    }
+
+    #[test]
+    fn palette_needs_four_hex_colors() {
+        let parse = |palette: &str| {
+            Options::parse(vec![String::from("--palette"), palette.to_string()].into_iter())
+        };
+        assert!(parse("000000,FFFFFF,FF0000").is_err());
+        assert!(parse("000000,FFFFFF,FF0000,GGGGGG").is_err());
+        assert!(parse("000000,FFFFFF,FF0000,FFF").is_err());
+    }
+
+    #[test]
+    fn no_window_is_an_alias_for_headless() {
+        for flag in ["--no-window", "--headless"].iter() {
+            let options = parse(&[flag, "PONG"]);
+            assert!(options.headless);
+            assert!(!options.opens_window());
+        }
+    }
+
+    #[test]
+    fn quit_key_is_resolved_by_name() {
+        assert_eq!(parse(&[]).quit_key, Key::Escape);
+        assert_eq!(parse(&["--quit-key", "F12"]).quit_key, Key::F12);
+
+        let unknown = Options::parse(["--quit-key", "nope"].iter().map(|arg| arg.to_string()));
+        assert_eq!(unknown.err(), Some(String::from("Unknown key: nope")));
+    }
+
+    #[test]
+    fn quit_key_cannot_be_a_mapped_key() {
+        let error = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string())).err();
+        assert_eq!(
+            error(&["--quit-key", "X"]),
+            Some(String::from("Quit key X is already mapped to key 0"))
+        );
+        // Checked against the chosen layout
+        assert_eq!(
+            error(&["--keymap", "sequential", "--quit-key", "X"]),
+            Some(String::from("Quit key X is already mapped to key D"))
+        );
+
+        let options = parse(&["--quit-key", "Escape"]);
+        assert_eq!(options.keymap.lookup(options.quit_key), None);
+    }
+
+    #[test]
+    fn sidecar_lines_become_flags() {
+        let text = "# per game settings\ncompat = \"schip\"\ncycle-accurate = false\n\
+                    quirk = [\"clip-sprites\", \"display-wait\"] # both needed\n\
+                    fg = \"#FF8000\"\nhz = 500 # fast\n";
+        assert_eq!(
+            sidecar_args(text).unwrap(),
+            [
+                "--compat",
+                "schip",
+                "--quirk",
+                "clip-sprites",
+                "--quirk",
+                "display-wait",
+                "--fg",
+                "#FF8000",
+                "--cycles-per-frame",
+                "8"
+            ]
+        );
+        assert_eq!(
+            sidecar_args("compat schip").err(),
+            Some(String::from("Invalid setting on line 1: compat schip"))
+        );
+    }
+
+    #[test]
+    fn sidecar_cannot_name_files() {
+        for setting in [
+            "trace = \"/tmp/out\"",
+            "log-file = \"/tmp/out\"",
+            "dump-state-on-exit = \"/tmp/out\"",
+            "record-input = \"/tmp/out\"",
+            "poke = \"0x200=0\"",
+            "strict = true",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                sidecar_args(&format!("compat = \"schip\"\n{}", setting)).err(),
+                Some(format!("Invalid setting on line 2: {}", setting))
+            );
+        }
+    }
+
+    // Options loaded from the command line for a ROM whose settings file
+    // holds `sidecar`
+    fn load_with_sidecar(name: &str, sidecar: &str, args: &[&str]) -> Options {
+        let rom = std::env::temp_dir().join(format!("rusty-chip8-{}.ch8", name));
+        let rom = rom.to_str().unwrap().to_string();
+        let path = sidecar_path(&rom);
+        std::fs::write(&rom, [0x12, 0x00]).unwrap();
+        std::fs::write(&path, sidecar).unwrap();
+
+        let mut full = vec![rom.clone()];
+        full.extend(args.iter().map(|arg| arg.to_string()));
+        let options = Options::load(full);
+        std::fs::remove_file(&rom).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        options.unwrap()
+    }
+
+    #[test]
+    fn sidecar_next_to_the_rom_is_applied_beneath_flags() {
+        let sidecar = "quirk = [\"display-wait\", \"clip-sprites\"]\n";
+
+        let quirks = load_with_sidecar("applied", sidecar, &[]).config.quirks;
+        assert!(quirks.display_wait && quirks.clip_sprites);
+
+        let overridden = load_with_sidecar("overridden", sidecar, &["--no-quirk", "clip-sprites"]);
+        let quirks = overridden.config.quirks;
+        assert!(quirks.display_wait && !quirks.clip_sprites);
+    }
+
+    #[test]
+    fn preset_flag_replaces_the_sidecar_quirks() {
+        let sidecar = "quirk = [\"shift-uses-vx\"]\ncompat = \"schip\"\n";
+        let options = load_with_sidecar("preset", sidecar, &["--compat", "cosmac"]);
+        assert_eq!(options.config.quirks, Quirks::preset("cosmac").unwrap());
+        assert!(!options.config.quirks.shift_uses_vx);
+    }
+
+    #[test]
+    fn sidecar_preset_settles_quirks_for_strict_mode() {
+        let options = load_with_sidecar("strict", "compat = \"schip\"\n", &["--strict"]);
+        assert!(options.config.strict);
+        assert!(options.config.ambiguous_quirks.is_empty());
+        assert_eq!(options.config.quirks, Quirks::preset("schip").unwrap());
+    }
+
+    #[test]
+    fn rates_of_zero_are_rejected() {
+        let parse_err =
+            |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string())).err();
+        assert_eq!(
+            parse_err(&["--cycles-per-frame", "0"]),
+            Some(String::from("Cycles per frame must be at least 1"))
+        );
+        assert_eq!(
+            parse_err(&["--slow", "0"]),
+            Some(String::from("Instructions per second must be at least 1"))
+        );
+        assert_eq!(parse(&["--cycles-per-frame", "8"]).cycles_per_frame, 8);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn list_names_the_archive_to_print() {
+        let options = parse(&["--list", "roms.zip"]);
+        assert_eq!(options.list_archive, Some(String::from("roms.zip")));
+        assert!(options.roms.is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::disasm;
+use crate::keymap::KEYPAD;
+
+// Debugging aids drawn over the output buffer after the display has been
+// copied in. They never touch the emulated display itself.
+
+// Colors of held and released keypad cells
+const KEY_DOWN: u32 = 0x00FF00;
+const KEY_UP: u32 = 0x404040;
+
+// Colors of overlay text and the box behind it
+const TEXT: u32 = 0xFFFF00;
+const TEXT_BACKGROUND: u32 = 0x000000;
+
+// Color of the border drawn while the sound timer runs
+const BEEP: u32 = 0xFF8000;
+
+// Size of a glyph, characters are separated by a one pixel gap
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+// Height of a line of text including the box drawn behind it
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+// Side length of a keypad cell, cells are separated by a one pixel gap
+const CELL: usize = 3;
+
+// Fills a w by h rectangle with its top left corner at (x, y), clipped to
+// the buffer
+pub fn fill_rect(
+    buffer: &mut [u32],
+    width: usize,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    color: u32,
+) {
+    let height = buffer.len() / width;
+
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            buffer[px + py * width] = color;
+        }
+    }
+}
+
+// Keypad laid out as on the original hardware, true where the key in that
+// cell is held
+pub fn keypad_cells(keys: &[bool; 16]) -> [[bool; 4]; 4] {
+    let mut cells = [[false; 4]; 4];
+
+    for (row, keypad_row) in KEYPAD.iter().enumerate() {
+        for (col, key) in keypad_row.iter().enumerate() {
+            cells[row][col] = keys[*key];
+        }
+    }
+
+    cells
+}
+
+// Draws the keypad in the top left corner with held keys lit
+pub fn draw_keypad(buffer: &mut [u32], width: usize, keys: &[bool; 16]) {
+    for (row, cells) in keypad_cells(keys).iter().enumerate() {
+        for (col, down) in cells.iter().enumerate() {
+            let color = if *down { KEY_DOWN } else { KEY_UP };
+            let x = 1 + col * (CELL + 1);
+            let y = 1 + row * (CELL + 1);
+            fill_rect(buffer, width, x, y, CELL, CELL, color);
+        }
+    }
+}
+
+// Register value as shown by --watch-reg, decimal then hex
+pub fn format_register(reg: usize, value: u8) -> String {
+    format!("{:X} {:>3} {:02X}", reg, value, value)
+}
+
+// Rows of a 3x5 glyph, most significant of the three bits on the left.
+// Covers what overlays need to show: digits, capitals, the punctuation
+// used by the disassembler and what's common in file names. Anything else
+// is blank.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        _ => [0; 5],
+    }
+}
+
+// Width in pixels of text drawn by draw_text, including its box
+fn text_width(text: &str) -> usize {
+    text.len() * (GLYPH_WIDTH + 1) + 1
+}
+
+// Draws a line of text on a box with its top left corner at (x, y)
+pub fn draw_text(buffer: &mut [u32], width: usize, x: usize, y: usize, text: &str) {
+    fill_rect(
+        buffer,
+        width,
+        x,
+        y,
+        text_width(text),
+        LINE_HEIGHT,
+        TEXT_BACKGROUND,
+    );
+
+    for (index, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    let px = x + 1 + index * (GLYPH_WIDTH + 1) + col;
+                    fill_rect(buffer, width, px, y + 1 + row, 1, 1, TEXT);
+                }
+            }
+        }
+    }
+}
+
+// Draws a register's value in the top right corner
+pub fn draw_register(buffer: &mut [u32], width: usize, reg: usize, value: u8) {
+    let text = format_register(reg, value);
+    draw_text(
+        buffer,
+        width,
+        width.saturating_sub(text_width(&text)),
+        0,
+        &text,
+    );
+}
+
+// Lines shown by the opcode overlay: the address and raw opcode, then its
+// mnemonic
+pub fn opcode_text(pc: u16, opcode: u16) -> [String; 2] {
+    [
+        format!("{:03X} {:04X}", pc, opcode),
+        disasm::mnemonic(opcode),
+    ]
+}
+
+// Draws the instruction at pc along the bottom of the display
+pub fn draw_opcode(buffer: &mut [u32], width: usize, pc: u16, opcode: u16) {
+    let height = buffer.len() / width;
+    let lines = opcode_text(pc, opcode);
+
+    for (index, line) in lines.iter().enumerate() {
+        let y = height.saturating_sub((lines.len() - index) * LINE_HEIGHT);
+        draw_text(buffer, width, 0, y, line);
+    }
+}
+
+// Draws a one pixel border around the display, shown while the ROM is
+// beeping
+pub fn draw_beep(buffer: &mut [u32], width: usize) {
+    let height = buffer.len() / width;
+
+    fill_rect(buffer, width, 0, 0, width, 1, BEEP);
+    fill_rect(buffer, width, 0, height.saturating_sub(1), width, 1, BEEP);
+    fill_rect(buffer, width, 0, 0, 1, height, BEEP);
+    fill_rect(buffer, width, width.saturating_sub(1), 0, 1, height, BEEP);
+}
+
+// Memory as a grid of brightness levels, one byte per cell when the grid
+// is big enough and the average of neighbouring bytes when it isn't
+pub fn memory_thumbnail(memory: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let per_cell = memory.len().div_ceil(width * height).max(1);
+
+    (0..width * height)
+        .map(|cell| {
+            let start = (cell * per_cell).min(memory.len());
+            let end = (start + per_cell).min(memory.len());
+            let bytes = &memory[start..end];
+            if bytes.is_empty() {
+                return 0;
+            }
+            (bytes.iter().map(|b| *b as usize).sum::<usize>() / bytes.len()) as u8
+        })
+        .collect()
+}
+
+// Draws memory as a grayscale square in the top left corner, shrunk to
+// fit the display height
+pub fn draw_memory(buffer: &mut [u32], width: usize, memory: &[u8]) {
+    let side = (buffer.len() / width).min(width).min(64);
+
+    for (cell, level) in memory_thumbnail(memory, side, side).iter().enumerate() {
+        let gray = *level as u32;
+        let color = gray << 16 | gray << 8 | gray;
+        fill_rect(buffer, width, cell % side, cell / side, 1, 1, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_keys_light_their_keypad_cells() {
+        let mut keys = [false; 16];
+        keys[0x1] = true;
+        keys[0x0] = true;
+        keys[0xF] = true;
+
+        let cells = keypad_cells(&keys);
+        assert_eq!(cells[0], [true, false, false, false]);
+        assert_eq!(cells[1], [false; 4]);
+        assert_eq!(cells[2], [false; 4]);
+        assert_eq!(cells[3], [false, true, false, true]);
+    }
+
+    #[test]
+    fn register_shows_decimal_then_hex() {
+        assert_eq!(format_register(0x3, 42), "3  42 2A");
+        assert_eq!(format_register(0xF, 255), "F 255 FF");
+        assert_eq!(format_register(0x0, 0), "0   0 00");
+    }
+
+    #[test]
+    fn memory_thumbnail_maps_and_averages_bytes() {
+        // One byte per pixel when memory fits
+        let memory: Vec<u8> = (0..16).collect();
+        assert_eq!(memory_thumbnail(&memory, 4, 4), memory);
+
+        // Neighbouring bytes are averaged when it doesn't
+        let memory = [0, 10, 20, 30, 40, 60, 100, 200];
+        assert_eq!(memory_thumbnail(&memory, 2, 2), vec![5, 25, 50, 150]);
+    }
+
+    #[test]
+    fn opcode_text_shows_address_opcode_and_mnemonic() {
+        assert_eq!(opcode_text(0x200, 0x6A0C), ["200 6A0C", "LD VA, 0C"]);
+        assert_eq!(opcode_text(0x2F4, 0x00E0), ["2F4 00E0", "CLS"]);
+    }
+}
@@ -0,0 +1,90 @@
+use std::path::Path;
+
+// ROMs given on the command line, switched between at runtime
+pub struct Playlist {
+    roms: Vec<String>,
+    index: usize,
+}
+
+impl Playlist {
+    pub fn new(roms: Vec<String>) -> Playlist {
+        Playlist { roms, index: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn current(&self) -> &str {
+        &self.roms[self.index]
+    }
+
+    // Advances to the next ROM, wrapping back to the first
+    pub fn next(&mut self) -> &str {
+        self.index = (self.index + 1) % self.roms.len();
+        self.current()
+    }
+
+    // Steps back to the previous ROM, wrapping around to the last
+    pub fn previous(&mut self) -> &str {
+        self.index = (self.index + self.roms.len() - 1) % self.roms.len();
+        self.current()
+    }
+}
+
+// Path of a ROM given on the command line. Relative paths are taken from
+// dir, absolute ones are used as they are.
+pub fn resolve(dir: &str, rom: &str) -> String {
+    if Path::new(rom).is_absolute() {
+        rom.to_string()
+    } else {
+        Path::new(dir).join(rom).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist() -> Playlist {
+        Playlist::new(vec![
+            String::from("PONG"),
+            String::from("TETRIS"),
+            String::from("UFO"),
+        ])
+    }
+
+    #[test]
+    fn next_wraps_to_the_first_rom() {
+        let mut playlist = playlist();
+        assert_eq!(playlist.current(), "PONG");
+        assert_eq!(playlist.next(), "TETRIS");
+        assert_eq!(playlist.next(), "UFO");
+        assert_eq!(playlist.next(), "PONG");
+    }
+
+    #[test]
+    fn previous_wraps_to_the_last_rom() {
+        let mut playlist = playlist();
+        assert_eq!(playlist.previous(), "UFO");
+        assert_eq!(playlist.previous(), "TETRIS");
+    }
+
+    #[test]
+    fn single_rom_stays_put() {
+        let mut playlist = Playlist::new(vec![String::from("PONG")]);
+        assert_eq!(playlist.next(), "PONG");
+        assert_eq!(playlist.previous(), "PONG");
+    }
+
+    #[test]
+    fn relative_roms_are_taken_from_the_directory() {
+        assert_eq!(resolve("roms", "PONG"), "roms/PONG");
+        assert_eq!(resolve("roms", "games/PONG"), "roms/games/PONG");
+    }
+
+    #[test]
+    fn absolute_roms_are_used_as_given() {
+        assert_eq!(resolve("roms", "/tmp/PONG"), "/tmp/PONG");
+    }
+}
@@ -0,0 +1,190 @@
+use crate::disasm;
+use crate::memory::MemoryBus;
+use std::cmp::Reverse;
+
+// Opcode categories the interpreter implements, matched as
+// (mask, value, name) against the raw opcode
+pub const OPCODES: [(u16, u16, &str); 39] = [
+    (0xFFF0, 0x00C0, "00CN"),
+    (0xFFFF, 0x00E0, "00E0"),
+    (0xFFFF, 0x00EE, "00EE"),
+    (0xFFFF, 0x00FB, "00FB"),
+    (0xFFFF, 0x00FC, "00FC"),
+    (0xFFFF, 0x00FE, "00FE"),
+    (0xFFFF, 0x00FF, "00FF"),
+    (0xF000, 0x1000, "1NNN"),
+    (0xF000, 0x2000, "2NNN"),
+    (0xF000, 0x3000, "3XNN"),
+    (0xF000, 0x4000, "4XNN"),
+    (0xF00F, 0x5000, "5XY0"),
+    (0xF000, 0x6000, "6XNN"),
+    (0xF000, 0x7000, "7XNN"),
+    (0xF00F, 0x8000, "8XY0"),
+    (0xF00F, 0x8001, "8XY1"),
+    (0xF00F, 0x8002, "8XY2"),
+    (0xF00F, 0x8003, "8XY3"),
+    (0xF00F, 0x8004, "8XY4"),
+    (0xF00F, 0x8005, "8XY5"),
+    (0xF00F, 0x8006, "8XY6"),
+    (0xF00F, 0x800E, "8XYE"),
+    (0xF00F, 0x9000, "9XY0"),
+    (0xF000, 0xA000, "ANNN"),
+    (0xF000, 0xB000, "BNNN"),
+    (0xF000, 0xC000, "CXNN"),
+    (0xF000, 0xD000, "DXYN"),
+    (0xF0FF, 0xE09E, "EX9E"),
+    (0xF0FF, 0xE0A1, "EXA1"),
+    (0xFFFF, 0xF000, "F000"),
+    (0xF0FF, 0xF007, "FX07"),
+    (0xF0FF, 0xF00A, "FX0A"),
+    (0xF0FF, 0xF015, "FX15"),
+    (0xF0FF, 0xF018, "FX18"),
+    (0xF0FF, 0xF01E, "FX1E"),
+    (0xF0FF, 0xF029, "FX29"),
+    (0xF0FF, 0xF033, "FX33"),
+    (0xF0FF, 0xF055, "FX55"),
+    (0xF0FF, 0xF065, "FX65"),
+];
+
+// Index into OPCODES for the given opcode
+pub fn category(opcode: u16) -> Option<usize> {
+    OPCODES
+        .iter()
+        .position(|(mask, value, _)| opcode & mask == *value)
+}
+
+// Lists which opcode categories were executed at least once and which
+// were never hit, given per-category execution counts
+pub fn coverage(counts: &[u64]) -> String {
+    let mut hit = Vec::new();
+    let mut missed = Vec::new();
+
+    for (count, (_, _, name)) in counts.iter().zip(OPCODES.iter()) {
+        if *count > 0 {
+            hit.push(*name);
+        } else {
+            missed.push(*name);
+        }
+    }
+
+    format!(
+        "Executed ({}/{}): {}\nNever hit: {}\n",
+        hit.len(),
+        OPCODES.len(),
+        hit.join(" "),
+        missed.join(" ")
+    )
+}
+
+// Lists the `top` most executed addresses, given per-address execution
+// counts, along with the instruction found at each
+pub fn hotspots(counts: &[u64], memory: &dyn MemoryBus, top: usize) -> String {
+    let mut hot: Vec<(usize, u64)> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(addr, count)| (addr, *count))
+        .collect();
+
+    // Ties are listed by address
+    hot.sort_by_key(|(addr, count)| (Reverse(*count), *addr));
+
+    let mut listing = String::new();
+    for (addr, count) in hot.into_iter().take(top) {
+        let hi = memory.read(addr).unwrap_or(0) as u16;
+        let lo = memory.read(addr + 1).unwrap_or(0) as u16;
+        let opcode = hi << 8 | lo;
+
+        listing.push_str(&format!(
+            "{:03X}  {:>10}  {:04X}  {}\n",
+            addr,
+            count,
+            opcode,
+            disasm::mnemonic(opcode)
+        ));
+    }
+
+    listing
+}
+
+// Longest loop body, in instructions, still treated as a busy-wait
+const BUSY_WAIT_LENGTH: usize = 4;
+
+// Whether the instructions from `start` up to the jump back at `jump` do
+// nothing but read the delay timer or keys and skip on the result
+pub fn is_busy_wait(memory: &dyn MemoryBus, start: usize, jump: usize) -> bool {
+    if (jump - start) / 2 > BUSY_WAIT_LENGTH {
+        return false;
+    }
+
+    // A jump to itself waits forever
+    if start == jump {
+        return true;
+    }
+
+    let mut polls = false;
+    for addr in (start..jump).step_by(2) {
+        let hi = memory.read(addr).unwrap_or(0) as u16;
+        let lo = memory.read(addr + 1).unwrap_or(0) as u16;
+
+        match (hi << 8 | lo) & 0xF0FF {
+            // FX07, EX9E, EXA1
+            0xF007 | 0xE09E | 0xE0A1 => polls = true,
+            // 3XNN, 4XNN, 5XY0, 9XY0
+            opcode if matches!(opcode & 0xF000, 0x3000 | 0x4000 | 0x5000 | 0x9000) => {}
+            _ => return false,
+        }
+    }
+
+    polls
+}
+
+// Summary of how much of a run was spent idle
+pub fn idle_ratio(idle: u64, cycles: u64) -> String {
+    let percent = if cycles == 0 {
+        0.0
+    } else {
+        idle as f64 * 100.0 / cycles as f64
+    };
+
+    format!("Idle cycles: {} of {} ({:.1}%)", idle, cycles, percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{MemFill, RamBus, CLASSIC_SIZE, ROM_START};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Memory holding `code` at the start of the ROM
+    fn memory(code: &[u8]) -> RamBus {
+        let mut memory = RamBus::new(CLASSIC_SIZE, MemFill::Zero, &mut StdRng::seed_from_u64(0));
+        for (i, byte) in code.iter().enumerate() {
+            memory.write(ROM_START + i, *byte).unwrap();
+        }
+        memory
+    }
+
+    #[test]
+    fn polling_loops_are_busy_waits() {
+        // F1 07, 31 00, 12 00
+        let timer = memory(&[0xF1, 0x07, 0x31, 0x00, 0x12, 0x00]);
+        assert!(is_busy_wait(&timer, 0x200, 0x204));
+
+        // E1 A1, 12 00
+        let key = memory(&[0xE1, 0xA1, 0x12, 0x00]);
+        assert!(is_busy_wait(&key, 0x200, 0x202));
+    }
+
+    #[test]
+    fn loops_doing_work_are_not_busy_waits() {
+        // F1 07, 70 01, 31 00, 12 00
+        let counting = memory(&[0xF1, 0x07, 0x70, 0x01, 0x31, 0x00, 0x12, 0x00]);
+        assert!(!is_busy_wait(&counting, 0x200, 0x206));
+
+        // Skips alone don't wait on anything: 30 00, 12 00
+        let skipping = memory(&[0x30, 0x00, 0x12, 0x00]);
+        assert!(!is_busy_wait(&skipping, 0x200, 0x202));
+    }
+}
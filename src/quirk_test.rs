@@ -0,0 +1,134 @@
+// Reads the results screen of the Timendus quirks test ROM
+// (https://github.com/Timendus/chip8-test-suite), which draws one row per
+// quirk with a check mark when the behavior matches the chosen platform
+// and a cross when it doesn't.
+
+// Memory location the ROM reads its platform from, skipping the menu it
+// would otherwise show. 1 selects the original CHIP-8.
+pub const PLATFORM_ADDR: usize = 0x1FF;
+pub const PLATFORM_CHIP8: u8 = 1;
+
+// Frames the display must stay unchanged for the results to be read
+pub const STABLE_FRAMES: u32 = 60;
+
+// Rectangle of the display, in lores pixels
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub name: &'static str,
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+// The bottom left stroke of each row's check mark. A cross leaves this
+// part of the cell dark, so the region is lit only when the row passed.
+pub const RESULTS: [Region; 6] = [
+    Region {
+        name: "vF reset",
+        x: 42,
+        y: 3,
+        w: 2,
+        h: 2,
+    },
+    Region {
+        name: "Memory",
+        x: 42,
+        y: 9,
+        w: 2,
+        h: 2,
+    },
+    Region {
+        name: "Display wait",
+        x: 42,
+        y: 15,
+        w: 2,
+        h: 2,
+    },
+    Region {
+        name: "Clipping",
+        x: 42,
+        y: 21,
+        w: 2,
+        h: 2,
+    },
+    Region {
+        name: "Shifting",
+        x: 42,
+        y: 27,
+        w: 2,
+        h: 2,
+    },
+    Region {
+        name: "Jumping",
+        x: 58,
+        y: 27,
+        w: 2,
+        h: 2,
+    },
+];
+
+// Whether any pixel of each region is lit, regions running off the display
+// are clipped
+pub fn read_regions(gfx: &[u32], width: usize, regions: &[Region]) -> Vec<bool> {
+    let height = gfx.len() / width;
+
+    regions
+        .iter()
+        .map(|region| {
+            (region.y..(region.y + region.h).min(height)).any(|y| {
+                (region.x..(region.x + region.w).min(width)).any(|x| gfx[x + y * width] != 0)
+            })
+        })
+        .collect()
+}
+
+// One line per quirk giving its result
+pub fn report(gfx: &[u32], width: usize) -> String {
+    let mut report = String::new();
+
+    for (region, passed) in RESULTS.iter().zip(read_regions(gfx, width, &RESULTS)) {
+        let result = if passed { "pass" } else { "FAIL" };
+        report.push_str(&format!("{:<12} {}\n", region.name, result));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_reads_each_result_region() {
+        let mut gfx = vec![0; 64 * 32];
+        // Check marks on the vF reset and Jumping rows
+        gfx[43 + 4 * 64] = 1;
+        gfx[58 + 27 * 64] = 1;
+
+        assert_eq!(
+            report(&gfx, 64),
+            "vF reset     pass\n\
+             Memory       FAIL\n\
+             Display wait FAIL\n\
+             Clipping     FAIL\n\
+             Shifting     FAIL\n\
+             Jumping      pass\n"
+        );
+    }
+
+    #[test]
+    fn regions_off_the_display_are_clipped() {
+        let region = Region {
+            name: "Edge",
+            x: 63,
+            y: 31,
+            w: 4,
+            h: 4,
+        };
+        let mut gfx = vec![0; 64 * 32];
+        assert_eq!(read_regions(&gfx, 64, &[region]), vec![false]);
+        gfx[63 + 31 * 64] = 1;
+        assert_eq!(read_regions(&gfx, 64, &[region]), vec![true]);
+    }
+}
@@ -0,0 +1,218 @@
+// Behaviors that differ between interpreters. Defaults follow the modern
+// consensus; individual ROMs may expect otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // FX0A completes when the key is released (as on the COSMAC VIP)
+    // rather than as soon as it is pressed
+    pub fx0a_on_release: bool,
+
+    // 00FE/00FF keep the current display (scaled to the new resolution)
+    // instead of clearing it
+    pub resolution_keeps_display: bool,
+
+    // Sprite pixels past the edge of the display are dropped rather than
+    // wrapped to the opposite edge
+    pub clip_sprites: bool,
+
+    // 8XY1, 8XY2 and 8XY3 clear VF after the operation (as on the COSMAC
+    // VIP, where they went through the ALU)
+    pub logic_resets_vf: bool,
+
+    // 8XY6 and 8XYE shift VX in place (as on SCHIP) rather than shifting
+    // VY into VX
+    pub shift_uses_vx: bool,
+
+    // FX55 and FX65 leave I pointing past the last register stored or
+    // loaded (as on the COSMAC VIP) instead of leaving it unchanged
+    pub load_store_increments_i: bool,
+
+    // BXNN jumps to XNN plus VX (as on SCHIP) rather than BNNN jumping to
+    // NNN plus V0
+    pub jump_uses_vx: bool,
+
+    // FX1E sets VF when I is pushed past 0xFFF (as on the Amiga
+    // interpreter)
+    pub fx1e_sets_vf: bool,
+
+    // DXYN waits for the next frame before the following instruction runs
+    // (as on the COSMAC VIP), limiting programs to one sprite per frame
+    pub display_wait: bool,
+
+    // 00CN, 00FB and 00FC scroll half as far in low resolution mode. SCHIP
+    // 1.1 always scrolled by high resolution pixels, so a low resolution
+    // scroll moved half a pixel, while SCHIP 1.0 and most later
+    // interpreters scroll by whole pixels at either resolution. Half pixels
+    // can't be shown here, so odd amounts round down.
+    pub half_pixel_scroll: bool,
+
+    // DXY0 in low resolution mode draws an 8x16 sprite (16 rows of one
+    // byte) rather than nothing. SCHIP only gives DXY0 a meaning in high
+    // resolution mode, where it's always a 16x16 sprite.
+    pub lores_dxy0_tall: bool,
+}
+
+// Preset names accepted by --compat
+pub const PRESETS: [&str; 4] = ["cosmac", "schip", "modern", "xochip"];
+
+// Names accepted by --quirk and --no-quirk
+pub const NAMES: [&str; 11] = [
+    "fx0a-on-release",
+    "resolution-keeps-display",
+    "clip-sprites",
+    "logic-resets-vf",
+    "shift-uses-vx",
+    "load-store-increments-i",
+    "jump-uses-vx",
+    "fx1e-sets-vf",
+    "display-wait",
+    "half-pixel-scroll",
+    "lores-dxy0-tall",
+];
+
+// Quirks that change what an opcode does
+pub fn affecting(opcode: u16) -> &'static [&'static str] {
+    match opcode & 0xF000 {
+        0x0000 if opcode == 0x00FE || opcode == 0x00FF => &["resolution-keeps-display"],
+        0x0000 if opcode & 0xFFF0 == 0x00C0 || opcode == 0x00FB || opcode == 0x00FC => {
+            &["half-pixel-scroll"]
+        }
+        0x8000 => match opcode & 0x000F {
+            0x1..=0x3 => &["logic-resets-vf"],
+            0x6 | 0xE => &["shift-uses-vx"],
+            _ => &[],
+        },
+        0xB000 => &["jump-uses-vx"],
+        0xD000 if opcode & 0x000F == 0 => &["clip-sprites", "display-wait", "lores-dxy0-tall"],
+        0xD000 => &["clip-sprites", "display-wait"],
+        0xF000 => match opcode & 0x00FF {
+            0x0A => &["fx0a-on-release"],
+            0x1E => &["fx1e-sets-vf"],
+            0x55 | 0x65 => &["load-store-increments-i"],
+            _ => &[],
+        },
+        _ => &[],
+    }
+}
+
+impl Quirks {
+    // Quirks of a well known interpreter
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            // The original interpreter on the COSMAC VIP
+            "cosmac" => Some(Quirks {
+                fx0a_on_release: true,
+                clip_sprites: true,
+                logic_resets_vf: true,
+                load_store_increments_i: true,
+                display_wait: true,
+                ..Quirks::default()
+            }),
+            // SUPER-CHIP 1.1 on the HP 48
+            "schip" => Some(Quirks {
+                resolution_keeps_display: true,
+                clip_sprites: true,
+                shift_uses_vx: true,
+                jump_uses_vx: true,
+                half_pixel_scroll: true,
+                ..Quirks::default()
+            }),
+            "modern" => Some(Quirks::default()),
+            // XO-CHIP as implemented by Octo
+            "xochip" => Some(Quirks {
+                load_store_increments_i: true,
+                ..Quirks::default()
+            }),
+            _ => None,
+        }
+    }
+
+    // Turns a single quirk on or off by its --quirk name
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        *self.field(name)? = on;
+
+        Ok(())
+    }
+
+    // Whether a single quirk is on, by its --quirk name
+    pub fn get(&self, name: &str) -> Result<bool, String> {
+        let mut quirks = *self;
+        quirks.field(name).map(|on| *on)
+    }
+
+    fn field(&mut self, name: &str) -> Result<&mut bool, String> {
+        let quirk = match name {
+            "fx0a-on-release" => &mut self.fx0a_on_release,
+            "resolution-keeps-display" => &mut self.resolution_keeps_display,
+            "clip-sprites" => &mut self.clip_sprites,
+            "logic-resets-vf" => &mut self.logic_resets_vf,
+            "shift-uses-vx" => &mut self.shift_uses_vx,
+            "load-store-increments-i" => &mut self.load_store_increments_i,
+            "jump-uses-vx" => &mut self.jump_uses_vx,
+            "fx1e-sets-vf" => &mut self.fx1e_sets_vf,
+            "display-wait" => &mut self.display_wait,
+            "half-pixel-scroll" => &mut self.half_pixel_scroll,
+            "lores-dxy0-tall" => &mut self.lores_dxy0_tall,
+            _ => {
+                return Err(format!(
+                    "Unknown quirk: {}, expected one of {}",
+                    name,
+                    NAMES.join(", ")
+                ))
+            }
+        };
+
+        Ok(quirk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Names of the quirks a preset turns on
+    fn enabled(preset: &str) -> Vec<&'static str> {
+        let quirks = Quirks::preset(preset).unwrap();
+        NAMES
+            .iter()
+            .copied()
+            .filter(|name| quirks.get(name).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn presets_turn_on_their_documented_quirks() {
+        assert_eq!(
+            enabled("cosmac"),
+            vec![
+                "fx0a-on-release",
+                "clip-sprites",
+                "logic-resets-vf",
+                "load-store-increments-i",
+                "display-wait",
+            ]
+        );
+        assert_eq!(
+            enabled("schip"),
+            vec![
+                "resolution-keeps-display",
+                "clip-sprites",
+                "shift-uses-vx",
+                "jump-uses-vx",
+                "half-pixel-scroll",
+            ]
+        );
+        assert!(enabled("modern").is_empty());
+        assert_eq!(enabled("xochip"), vec!["load-store-increments-i"]);
+        assert_eq!(Quirks::preset("chip48"), None);
+    }
+
+    #[test]
+    fn quirks_are_set_by_name() {
+        let mut quirks = Quirks::default();
+        for name in NAMES.iter() {
+            quirks.set(name, true).unwrap();
+            assert!(quirks.get(name).unwrap());
+        }
+        assert!(quirks.set("wrap-everything", true).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::input::ScriptedInput;
+use crate::timing::FrameBudget;
+use crate::{run_headless, Config, CPU};
+
+// Tiny ROMs each exercising one opcode, ending in a jump to themselves so
+// the headless runner stops, along with the register values they should
+// leave behind
+struct Case {
+    name: &'static str,
+    rom: &'static [u8],
+    regs: &'static [(usize, u8)],
+    i: Option<u16>,
+}
+
+const CASES: [Case; 11] = [
+    Case {
+        name: "6XNN",
+        rom: &[0x60, 0x2A, 0x12, 0x02],
+        regs: &[(0x0, 0x2A)],
+        i: None,
+    },
+    Case {
+        name: "7XNN",
+        rom: &[0x60, 0xFF, 0x70, 0x02, 0x12, 0x04],
+        regs: &[(0x0, 0x01), (0xF, 0x00)],
+        i: None,
+    },
+    Case {
+        name: "8XY2",
+        rom: &[0x60, 0x0F, 0x61, 0x3C, 0x80, 0x12, 0x12, 0x06],
+        regs: &[(0x0, 0x0C)],
+        i: None,
+    },
+    Case {
+        name: "8XY4",
+        rom: &[0x60, 0xFF, 0x61, 0x02, 0x80, 0x14, 0x12, 0x06],
+        regs: &[(0x0, 0x01), (0xF, 0x01)],
+        i: None,
+    },
+    Case {
+        name: "8XY5",
+        rom: &[0x60, 0x01, 0x61, 0x02, 0x80, 0x15, 0x12, 0x06],
+        regs: &[(0x0, 0xFF), (0xF, 0x00)],
+        i: None,
+    },
+    Case {
+        name: "3XNN",
+        rom: &[0x60, 0x05, 0x30, 0x05, 0x61, 0x01, 0x62, 0x01, 0x12, 0x08],
+        regs: &[(0x1, 0x00), (0x2, 0x01)],
+        i: None,
+    },
+    // Whatever the random number, masking with 0x00 must clear VX
+    Case {
+        name: "CXNN",
+        rom: &[0x60, 0xFF, 0xC0, 0x00, 0x12, 0x04],
+        regs: &[(0x0, 0x00)],
+        i: None,
+    },
+    Case {
+        name: "2NNN/00EE",
+        rom: &[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x07, 0x00, 0xEE],
+        regs: &[(0x0, 0x07), (0x1, 0x01)],
+        i: None,
+    },
+    Case {
+        name: "FX1E",
+        rom: &[0xA1, 0x00, 0x60, 0x10, 0xF0, 0x1E, 0x12, 0x06],
+        regs: &[],
+        i: Some(0x110),
+    },
+    Case {
+        name: "FX29",
+        rom: &[0x60, 0x0A, 0xF0, 0x29, 0x12, 0x04],
+        regs: &[],
+        i: Some(0x82),
+    },
+    Case {
+        name: "FX33/FX65",
+        rom: &[0x60, 0xFE, 0xA3, 0x00, 0xF0, 0x33, 0xF2, 0x65, 0x12, 0x08],
+        regs: &[(0x0, 0x02), (0x1, 0x05), (0x2, 0x04)],
+        i: Some(0x300),
+    },
+];
+
+// Instructions a case may run before it counts as hung
+const MAX_CYCLES: u64 = 1000;
+
+// Runs a case, describing the first difference from what was expected
+fn run_case(case: &Case) -> Result<(), String> {
+    let config = Config::default();
+    let mut cpu = CPU::from_rom(case.rom, vec![0; 64 * 32], &config).map_err(|e| e.to_string())?;
+
+    let budget = FrameBudget {
+        limit: 1,
+        cycle_accurate: false,
+    };
+    run_headless(
+        &mut cpu,
+        &mut ScriptedInput::default(),
+        MAX_CYCLES,
+        budget,
+        |_, _| false,
+    )
+    .map_err(|e| e.to_string())?;
+
+    for (reg, expected) in case.regs {
+        if cpu.V[*reg] != *expected {
+            return Err(format!(
+                "V{:X} is {:02X}, expected {:02X}",
+                reg, cpu.V[*reg], expected
+            ));
+        }
+    }
+
+    if let Some(expected) = case.i {
+        if cpu.I != expected {
+            return Err(format!("I is {:03X}, expected {:03X}", cpu.I, expected));
+        }
+    }
+
+    Ok(())
+}
+
+// Runs every case, returning a line per case and whether they all passed
+pub fn run() -> (String, bool) {
+    let mut report = String::new();
+    let mut passed = 0;
+
+    for case in CASES.iter() {
+        match run_case(case) {
+            Ok(()) => {
+                report.push_str(&format!("PASS {}\n", case.name));
+                passed += 1;
+            }
+            Err(e) => report.push_str(&format!("FAIL {}: {}\n", case.name, e)),
+        }
+    }
+    report.push_str(&format!("{}/{} passed\n", passed, CASES.len()));
+
+    (report, passed == CASES.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_case_passes() {
+        let (report, passed) = run();
+        assert!(passed, "{}", report);
+        assert!(report.ends_with(&format!("{0}/{0} passed\n", CASES.len())));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::error::EmulateError;
+use crate::CPU;
+use log::warn;
+
+// Full CPU state as a JSON object, written for post-mortem debugging or to
+// resume from later. The display is packed 8 pixels to a byte and given as
+// a hex string, as is memory.
+pub fn to_json(cpu: &CPU) -> String {
+    let regs: Vec<String> = cpu.V.iter().map(|v| v.to_string()).collect();
+    let stack: Vec<String> = cpu.stack.iter().map(|s| s.to_string()).collect();
+    let display: String = cpu
+        .pack_display()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let memory: String = (0..cpu.memory.size())
+        .map(|addr| format!("{:02X}", cpu.memory.read(addr).unwrap_or(0)))
+        .collect();
+
+    format!(
+        "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"i\":{},\"sp\":{},\"stack\":[{}],\"dt\":{},\"st\":{},\"v\":[{}],\"halted\":{},\"width\":{},\"height\":{},\"display\":\"{}\",\"rom\":\"{:016X}\",\"memory\":\"{}\"}}\n",
+        cpu.cycles,
+        cpu.pc,
+        cpu.opcode,
+        cpu.I,
+        cpu.sp,
+        stack.join(","),
+        cpu.delay_timer,
+        cpu.sound_timer,
+        regs.join(","),
+        cpu.halted,
+        cpu.width,
+        cpu.height,
+        display,
+        cpu.rom_hash,
+        memory
+    )
+}
+
+// Restores a state written by to_json. A state saved for a different ROM
+// is still loaded, with a warning.
+pub fn load_state(cpu: &mut CPU, json: &str) -> Result<(), EmulateError> {
+    let rom = u64::from_str_radix(string(json, "rom")?, 16).map_err(|_| invalid("rom"))?;
+    if rom != cpu.rom_hash {
+        warn!(
+            "State was saved for a different ROM ({:016X}, loaded {:016X})",
+            rom, cpu.rom_hash
+        );
+    }
+
+    // Everything is checked before any of it is applied, so a bad file
+    // leaves the CPU as it was
+    let memory = hex(json, "memory")?;
+    if memory.len() != cpu.memory.size() {
+        return Err(invalid("memory"));
+    }
+
+    let stack: Vec<u16> = list(json, "stack")?;
+    if stack.len() != cpu.stack.len() {
+        return Err(invalid("stack"));
+    }
+    let sp: u8 = number(json, "sp")?;
+    if sp as usize > stack.len() {
+        return Err(invalid("sp"));
+    }
+    let v: Vec<u8> = list(json, "v")?;
+    if v.len() != cpu.V.len() {
+        return Err(invalid("v"));
+    }
+
+    let pc: u16 = number(json, "pc")?;
+    if pc as usize >= memory.len() {
+        return Err(invalid("pc"));
+    }
+    let i: u16 = number(json, "i")?;
+    if i as usize >= memory.len() {
+        return Err(invalid("i"));
+    }
+
+    let width: u32 = number(json, "width")?;
+    let height: u32 = number(json, "height")?;
+    if (width, height) != (64, 32) && (width, height) != (128, 64) {
+        return Err(invalid("width"));
+    }
+    let display = hex(json, "display")?;
+    if display.len() != (width * height / 8) as usize {
+        return Err(invalid("display"));
+    }
+
+    let cycles = number(json, "cycle")?;
+    let opcode = number(json, "opcode")?;
+    let delay_timer = number(json, "dt")?;
+    let sound_timer = number(json, "st")?;
+    let halted = number(json, "halted")?;
+
+    for (addr, byte) in memory.into_iter().enumerate() {
+        cpu.memory.write(addr, byte)?;
+    }
+    cpu.hires = width == 128;
+    cpu.width = width;
+    cpu.height = height;
+    cpu.gfx = vec![0; (width * height) as usize];
+    cpu.unpack_display(&display)?;
+
+    cpu.cycles = cycles;
+    cpu.pc = pc;
+    cpu.opcode = opcode;
+    cpu.I = i;
+    cpu.sp = sp;
+    cpu.stack = stack;
+    cpu.delay_timer = delay_timer;
+    cpu.sound_timer = sound_timer;
+    cpu.V.copy_from_slice(&v);
+    cpu.halted = halted;
+
+    Ok(())
+}
+
+fn invalid(name: &str) -> EmulateError {
+    EmulateError::InvalidState(format!("bad or missing \"{}\"", name))
+}
+
+// Raw text of a field's value, up to the next comma or closing brace. The
+// state is flat apart from arrays, which hold no nested commas to skip.
+fn field<'a>(json: &'a str, name: &str) -> Result<&'a str, EmulateError> {
+    let key = format!("\"{}\":", name);
+    let start = json.find(&key).ok_or_else(|| invalid(name))? + key.len();
+    let rest = &json[start..];
+
+    let end = if rest.starts_with('[') {
+        rest.find(']').map(|end| end + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+
+    Ok(&rest[..end.ok_or_else(|| invalid(name))?])
+}
+
+fn number<T: std::str::FromStr>(json: &str, name: &str) -> Result<T, EmulateError> {
+    field(json, name)?.parse().map_err(|_| invalid(name))
+}
+
+fn string<'a>(json: &'a str, name: &str) -> Result<&'a str, EmulateError> {
+    let value = field(json, name)?;
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| invalid(name))
+}
+
+fn list<T: std::str::FromStr>(json: &str, name: &str) -> Result<Vec<T>, EmulateError> {
+    let value = field(json, name)?;
+    let items = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .ok_or_else(|| invalid(name))?;
+
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    items
+        .split(',')
+        .map(|item| item.parse().map_err(|_| invalid(name)))
+        .collect()
+}
+
+// Bytes of a hex string field, two digits each
+fn hex(json: &str, name: &str) -> Result<Vec<u8>, EmulateError> {
+    let value = string(json, name)?;
+    if value.len() % 2 != 0 {
+        return Err(invalid(name));
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| invalid(name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ScriptedInput;
+    use crate::timing::FrameBudget;
+    use crate::{run_headless, Config};
+
+    // Sets a few registers and I, calls a subroutine, then stops inside it
+    const ROM: [u8; 10] = [0x60, 0x2A, 0x6E, 0x07, 0xA3, 0x21, 0x22, 0x08, 0x12, 0x08];
+
+    fn cpu() -> CPU {
+        CPU::from_rom(&ROM, vec![0; 64 * 32], &Config::default()).unwrap()
+    }
+
+    fn saved() -> (CPU, String) {
+        let mut cpu = cpu();
+        for _ in 0..5 {
+            cpu.emulate_cycle().unwrap();
+        }
+        cpu.gfx[70] = 1;
+        cpu.delay_timer = 9;
+        let json = to_json(&cpu);
+        (cpu, json)
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let (saved, json) = saved();
+
+        let mut restored = cpu();
+        load_state(&mut restored, &json).unwrap();
+
+        assert_eq!(restored.pc, 0x208);
+        assert_eq!(restored.pc, saved.pc);
+        assert_eq!(restored.I, 0x321);
+        assert_eq!(restored.sp, 1);
+        assert_eq!(restored.stack, saved.stack);
+        assert_eq!(restored.V, saved.V);
+        assert_eq!(restored.delay_timer, 9);
+        assert_eq!(restored.cycles, 5);
+        assert_eq!(restored.gfx, saved.gfx);
+        assert_eq!(to_json(&restored), json);
+    }
+
+    #[test]
+    fn state_for_another_rom_still_loads() {
+        let (_, json) = saved();
+
+        let mut other = CPU::from_rom(&[0x12, 0x00], vec![0; 64 * 32], &Config::default()).unwrap();
+        load_state(&mut other, &json).unwrap();
+        assert_eq!(other.pc, 0x208);
+    }
+
+    #[test]
+    fn out_of_range_fields_are_refused() {
+        let (_, json) = saved();
+
+        for (from, to, name) in [
+            ("\"sp\":1", "\"sp\":200", "sp"),
+            ("\"pc\":520", "\"pc\":4096", "pc"),
+            ("\"i\":801", "\"i\":5000", "i"),
+            ("\"width\":64", "\"width\":65", "width"),
+        ]
+        .iter()
+        {
+            let mut cpu = cpu();
+            let bad = json.replacen(from, to, 1);
+            assert_ne!(bad, json);
+            assert_eq!(load_state(&mut cpu, &bad), Err(invalid(name)));
+            assert_eq!(cpu.pc, 0x200);
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_refused() {
+        let mut cpu = cpu();
+        assert_eq!(load_state(&mut cpu, "{\"pc\":1}"), Err(invalid("rom")));
+    }
+
+    #[test]
+    fn dump_after_a_headless_run_holds_the_final_pc() {
+        let mut cpu = cpu();
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        run_headless(
+            &mut cpu,
+            &mut ScriptedInput::default(),
+            100,
+            budget,
+            |_, _| false,
+        )
+        .unwrap();
+
+        // Halted on the jump to itself at 0x208
+        let json = to_json(&cpu);
+        assert!(json.contains("\"pc\":520"), "{}", json);
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+
+// Off and on colors sampled from an image. Images are read as binary PPM
+// (P6), which needs no decoder crate and which most image editors export.
+
+// Pixels of a binary PPM as 0xRRGGBB
+pub fn parse_ppm(data: &[u8]) -> Result<Vec<u32>, String> {
+    // The header is four whitespace separated fields, comments start with #
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while fields.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos < data.len() && data[pos] == b'#' {
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(String::from("Truncated PPM header"));
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+
+    if fields[0] != "P6" {
+        return Err(String::from("Only binary PPM (P6) images are supported"));
+    }
+
+    let number = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid PPM header field: {}", field))
+    };
+    let width = number(&fields[1])?;
+    let height = number(&fields[2])?;
+    if number(&fields[3])? != 255 {
+        return Err(String::from("Only 8 bit PPM images are supported"));
+    }
+
+    // A single whitespace byte separates the header from the pixels
+    let pixels = data.get(pos + 1..).unwrap_or(&[]);
+    if pixels.len() < width * height * 3 {
+        return Err(String::from("Truncated PPM pixel data"));
+    }
+
+    Ok(pixels
+        .chunks(3)
+        .take(width * height)
+        .map(|rgb| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32)
+        .collect())
+}
+
+// The two most common colors as (off, on), the darker one being off.
+// Similar colors are counted together by grouping on the top four bits of
+// each channel, and each group is represented by its average color.
+pub fn dominant_colors(pixels: &[u32]) -> Option<(u32, u32)> {
+    // Pixel count and channel sums for each group
+    let mut groups = vec![(0u64, [0u64; 3]); 4096];
+
+    for pixel in pixels {
+        let channels = [(pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF];
+        let group = (channels[0] >> 4) << 8 | (channels[1] >> 4) << 4 | channels[2] >> 4;
+
+        let (count, sums) = &mut groups[group as usize];
+        *count += 1;
+        for (sum, channel) in sums.iter_mut().zip(channels.iter()) {
+            *sum += *channel as u64;
+        }
+    }
+
+    let mut ranked: Vec<&(u64, [u64; 3])> = groups.iter().filter(|(count, _)| *count > 0).collect();
+    ranked.sort_by_key(|(count, _)| Reverse(*count));
+
+    let average = |(count, sums): &(u64, [u64; 3])| {
+        sums.iter()
+            .fold(0, |color, sum| color << 8 | (sum / count) as u32)
+    };
+
+    let first = average(ranked.first()?);
+    let second = average(ranked.get(1)?);
+
+    if luminance(first) <= luminance(second) {
+        Some((first, second))
+    } else {
+        Some((second, first))
+    }
+}
+
+// Perceived brightness, weighted as in Rec. 601
+fn luminance(color: u32) -> u32 {
+    let r = (color >> 16) & 0xFF;
+    let g = (color >> 8) & 0xFF;
+    let b = color & 0xFF;
+    r * 299 + g * 587 + b * 114
+}
+
+// Off and on colors of the image at path
+pub fn load(path: &str) -> Result<(u32, u32), String> {
+    let data = std::fs::read(path).map_err(|e| format!("Unable to read theme {}: {}", path, e))?;
+    let pixels = parse_ppm(&data)?;
+
+    dominant_colors(&pixels).ok_or_else(|| format!("Theme needs at least two colors: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Binary PPM of the given pixels in a single row
+    fn ppm(pixels: &[u32]) -> Vec<u8> {
+        let mut data = format!("P6\n# synthetic\n{} 1\n255\n", pixels.len()).into_bytes();
+        for pixel in pixels {
+            data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+        data
+    }
+
+    #[test]
+    fn two_color_image_gives_its_off_and_on_colors() {
+        let light = 0xE0F0D0;
+        let dark = 0x102030;
+        let pixels = [light, dark, dark, light, dark, light, light, light];
+
+        let parsed = parse_ppm(&ppm(&pixels)).unwrap();
+        assert_eq!(parsed, pixels);
+
+        // Darker is off however much of the image it covers
+        assert_eq!(dominant_colors(&parsed), Some((dark, light)));
+    }
+
+    #[test]
+    fn similar_colors_count_together() {
+        let pixels = [0xFF0000, 0xF80404, 0xFC0202, 0x000000, 0x0000FF, 0x0000FF];
+        assert_eq!(dominant_colors(&pixels), Some((0x0000FF, 0xFB0202)));
+    }
+
+    #[test]
+    fn single_color_image_has_no_theme() {
+        assert_eq!(dominant_colors(&[0x123456; 4]), None);
+    }
+
+    #[test]
+    fn only_8_bit_binary_ppm_is_read() {
+        assert!(parse_ppm(b"P3\n1 1\n255\n0 0 0").is_err());
+        assert!(parse_ppm(b"P6\n1 1\n65535\n\0\0\0\0\0\0").is_err());
+        assert!(parse_ppm(b"P6\n2 1\n255\n\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+// Source of the current time, so frames can be timed against the real
+// clock or a simulated one
+pub trait Clock {
+    // Time since the clock started
+    fn now(&self) -> Duration;
+
+    fn elapsed(&self, since: Duration) -> Duration {
+        self.now().saturating_sub(since)
+    }
+}
+
+// Wall clock time
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// Time that only moves when advanced, for runs that don't follow real time
+#[derive(Default)]
+pub struct MockClock {
+    now: Duration,
+}
+
+impl MockClock {
+    pub fn advance(&mut self, dt: Duration) {
+        self.now += dt;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+// How much work is done in each 60 Hz frame
+#[derive(Clone, Copy, Debug)]
+pub struct FrameBudget {
+    pub limit: u32,
+
+    // Count COSMAC VIP machine cycles rather than instructions
+    pub cycle_accurate: bool,
+}
+
+impl FrameBudget {
+    // Share of the budget used by an instruction
+    pub fn cost(&self, opcode: u16) -> u32 {
+        if self.cycle_accurate {
+            cost(opcode)
+        } else {
+            1
+        }
+    }
+}
+
+// Spreads instructions out at a fixed rate in real time, independent of
+// the frame rate, carrying time that isn't yet worth an instruction over to
+// the next call
+pub struct Throttle {
+    delay: Duration,
+    elapsed: Duration,
+}
+
+impl Throttle {
+    // Throttle running `rate` instructions per second
+    pub fn new(rate: u32) -> Throttle {
+        Throttle {
+            delay: cycle_delay(rate),
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    // Number of instructions due now that dt has passed
+    pub fn due(&mut self, dt: Duration) -> u32 {
+        self.elapsed += dt;
+
+        let mut due = 0;
+        while self.elapsed >= self.delay {
+            self.elapsed -= self.delay;
+            due += 1;
+        }
+
+        due
+    }
+}
+
+// Time between instructions at `rate` instructions per second
+pub fn cycle_delay(rate: u32) -> Duration {
+    Duration::from_secs(1) / rate.max(1)
+}
+
+// Machine cycles the VIP gets through in one 60 Hz frame
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// Approximate COSMAC VIP machine cycles taken by an opcode. Draws and the
+// memory block opcodes scale with how much they touch.
+pub fn cost(opcode: u16) -> u32 {
+    let X = ((opcode & 0x0F00) >> 8) as u32;
+    let N = (opcode & 0x000F) as u32;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => 24,
+            0x00EE => 10,
+            _ => 10,
+        },
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 10,
+        0x5000 | 0x9000 => 14,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        0xD000 => 22 + N * 34,
+        0xE000 => 14,
+        _ => match opcode & 0x00FF {
+            0x0007 | 0x0015 | 0x0018 => 10,
+            0x001E => 16,
+            0x0029 => 20,
+            0x0033 => 84,
+            0x0055 | 0x0065 => 14 + (X + 1) * 14,
+            _ => 10,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_costs_more_per_row() {
+        assert_eq!(cost(0xD001), 22 + 34);
+        assert_eq!(cost(0xD005), 22 + 5 * 34);
+        assert_eq!(cost(0x6001), 6);
+    }
+
+    #[test]
+    fn only_cycle_accurate_budgets_use_the_cost() {
+        let accurate = FrameBudget {
+            limit: VIP_CYCLES_PER_FRAME,
+            cycle_accurate: true,
+        };
+        let counted = FrameBudget {
+            limit: 10,
+            cycle_accurate: false,
+        };
+        assert_eq!(accurate.cost(0xD005), 192);
+        assert_eq!(counted.cost(0xD005), 1);
+    }
+
+    #[test]
+    fn cycle_delay_spreads_a_second_over_the_rate() {
+        assert_eq!(cycle_delay(5), Duration::from_millis(200));
+        assert_eq!(cycle_delay(1), Duration::from_secs(1));
+        // A rate of 0 is treated as 1 rather than dividing by zero
+        assert_eq!(cycle_delay(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn throttle_carries_time_over_between_calls() {
+        let mut throttle = Throttle::new(5);
+        assert_eq!(throttle.due(Duration::from_millis(150)), 0);
+        assert_eq!(throttle.due(Duration::from_millis(150)), 1);
+        assert_eq!(throttle.due(Duration::from_millis(500)), 3);
+    }
+}
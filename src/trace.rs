@@ -0,0 +1,240 @@
+use crate::error::EmulateError;
+use crate::{CPU, FRAME};
+
+// Line layouts a trace can be written in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TraceFormat {
+    #[default]
+    Default,
+    // Register dump in the style of Octo's debugger
+    Octo,
+    // One JSON object per instruction
+    Json,
+}
+
+// State after an instruction has executed, one line per cycle
+pub fn format_state(cpu: &CPU, format: TraceFormat) -> String {
+    match format {
+        TraceFormat::Default => {
+            let regs: Vec<String> = cpu.V.iter().map(|v| format!("{:02X}", v)).collect();
+
+            format!(
+                "{} PC:{:04X} OP:{:04X} I:{:04X} SP:{:X} DT:{:02X} ST:{:02X} V:{}",
+                cpu.cycles,
+                cpu.pc,
+                cpu.opcode,
+                cpu.I,
+                cpu.sp,
+                cpu.delay_timer,
+                cpu.sound_timer,
+                regs.join(" ")
+            )
+        }
+        TraceFormat::Octo => {
+            let regs: Vec<String> = cpu
+                .V
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("v{:x}={:02x}", i, v))
+                .collect();
+
+            format!(
+                "pc={:04x} op={:04x} i={:04x} {} dt={:02x} st={:02x}",
+                cpu.pc,
+                cpu.opcode,
+                cpu.I,
+                regs.join(" "),
+                cpu.delay_timer,
+                cpu.sound_timer
+            )
+        }
+        TraceFormat::Json => {
+            let regs: Vec<String> = cpu.V.iter().map(|v| v.to_string()).collect();
+
+            format!(
+                "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"i\":{},\"sp\":{},\"dt\":{},\"st\":{},\"v\":[{}]}}",
+                cpu.cycles,
+                cpu.pc,
+                cpu.opcode,
+                cpu.I,
+                cpu.sp,
+                cpu.delay_timer,
+                cpu.sound_timer,
+                regs.join(",")
+            )
+        }
+    }
+}
+
+// First cycle where a run stopped matching the reference trace
+pub struct Divergence {
+    pub cycle: u64,
+    pub expected: String,
+    pub actual: String,
+}
+
+// Runs two CPUs in lockstep, comparing their states after every
+// instruction, until one diverges or max_cycles have run. The first CPU's
+// state is reported as expected.
+pub fn compare(
+    first: &mut CPU,
+    second: &mut CPU,
+    max_cycles: u64,
+) -> Result<Option<Divergence>, EmulateError> {
+    while first.cycles < max_cycles && !(first.halted && second.halted) {
+        for cpu in [&mut *first, &mut *second].iter_mut() {
+            cpu.emulate_cycle()?;
+            cpu.tick_timers(FRAME);
+        }
+
+        let expected = format_state(first, TraceFormat::Default);
+        let actual = format_state(second, TraceFormat::Default);
+        if expected != actual || first.gfx != second.gfx {
+            return Ok(Some(Divergence {
+                cycle: first.cycles,
+                expected,
+                actual,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+// Runs one cycle per line of the reference trace, comparing states as it
+// goes. Like --trace, the state is taken before the timers tick.
+pub fn diff_trace(cpu: &mut CPU, reference: &str) -> Result<Option<Divergence>, EmulateError> {
+    for expected in reference.lines() {
+        cpu.emulate_cycle()?;
+
+        let actual = format_state(cpu, cpu.trace_format);
+        cpu.tick_timers(FRAME);
+        if actual != expected {
+            return Ok(Some(Divergence {
+                cycle: cpu.cycles,
+                expected: expected.to_string(),
+                actual,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ScriptedInput;
+    use crate::quirks::Quirks;
+    use crate::timing::FrameBudget;
+    use crate::{run_headless, Config};
+    use std::fs::{self, File};
+    use std::io::BufWriter;
+
+    // Counts the delay timer down from 16 then stops, so the timers show
+    // up in every line
+    const DELAY_LOOP: [u8; 12] = [
+        0x60, 0x10, 0xF0, 0x15, 0xF0, 0x07, 0x30, 0x00, 0x12, 0x04, 0x12, 0x0A,
+    ];
+
+    fn cpu() -> CPU {
+        CPU::from_rom(&DELAY_LOOP, vec![0; 64 * 32], &Config::default()).unwrap()
+    }
+
+    #[test]
+    fn trace_from_a_run_matches_itself() {
+        let path = std::env::temp_dir().join("rusty-chip8-self-trace.txt");
+
+        let mut writer = cpu();
+        writer.trace = Some(BufWriter::new(File::create(&path).unwrap()));
+        let budget = FrameBudget {
+            limit: 1,
+            cycle_accurate: false,
+        };
+        run_headless(
+            &mut writer,
+            &mut ScriptedInput::default(),
+            1000,
+            budget,
+            |_, _| false,
+        )
+        .unwrap();
+        writer.trace = None;
+
+        let reference = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(reference.lines().count() > 16);
+
+        let divergence = diff_trace(&mut cpu(), &reference).unwrap();
+        assert!(divergence.is_none());
+    }
+
+    #[test]
+    fn diff_trace_reports_the_first_changed_line() {
+        let mut reference = String::new();
+        let mut first = cpu();
+        for _ in 0..5 {
+            first.emulate_cycle().unwrap();
+            reference.push_str(&format_state(&first, TraceFormat::Default));
+            reference.push('\n');
+            first.tick_timers(FRAME);
+        }
+        let reference = reference.replacen("V:10", "V:11", 1);
+
+        let divergence = diff_trace(&mut cpu(), &reference).unwrap().unwrap();
+        assert_eq!(divergence.cycle, 1);
+        assert!(divergence
+            .expected
+            .ends_with("V:11 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"));
+    }
+
+    #[test]
+    fn first_line_in_each_format() {
+        let mut cpu = cpu();
+        cpu.emulate_cycle().unwrap();
+
+        assert_eq!(
+            format_state(&cpu, TraceFormat::Default),
+            "1 PC:0202 OP:6010 I:0000 SP:0 DT:00 ST:00 \
+             V:10 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+        );
+        assert_eq!(
+            format_state(&cpu, TraceFormat::Octo),
+            "pc=0202 op=6010 i=0000 v0=10 v1=00 v2=00 v3=00 v4=00 v5=00 v6=00 v7=00 \
+             v8=00 v9=00 va=00 vb=00 vc=00 vd=00 ve=00 vf=00 dt=00 st=00"
+        );
+        assert_eq!(
+            format_state(&cpu, TraceFormat::Json),
+            "{\"cycle\":1,\"pc\":514,\"opcode\":24592,\"i\":0,\"sp\":0,\"dt\":0,\"st\":0,\
+             \"v\":[16,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"
+        );
+    }
+
+    #[test]
+    fn compare_diverges_at_the_first_shift() {
+        // 60 03, 61 04, 80 16, then halt
+        let rom = [0x60, 0x03, 0x61, 0x04, 0x80, 0x16, 0x12, 0x06];
+        let with_quirk = |shift_uses_vx| {
+            let config = Config {
+                quirks: Quirks {
+                    shift_uses_vx,
+                    ..Quirks::default()
+                },
+                ..Config::default()
+            };
+            CPU::from_rom(&rom, vec![0; 64 * 32], &config).unwrap()
+        };
+
+        let divergence = compare(&mut with_quirk(true), &mut with_quirk(false), 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.cycle, 3);
+        assert!(divergence.expected.contains("OP:8016"));
+        // With the quirk V0 (3) is shifted and VF gets its low bit. Without
+        // it V1 (4) is shifted and VF gets a 0.
+        let expected_regs = "V:01 04 00 00 00 00 00 00 00 00 00 00 00 00 00 01";
+        let actual_regs = "V:02 04 00 00 00 00 00 00 00 00 00 00 00 00 00 00";
+        assert!(divergence.expected.ends_with(expected_regs));
+        assert!(divergence.actual.ends_with(actual_regs));
+    }
+}